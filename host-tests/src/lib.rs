@@ -0,0 +1,29 @@
+//! Builds the modules of the firmware which don't depend on the hardware for the host,
+//! so their unit tests can run without a scope.
+
+#![no_std]
+// Only the parts used by the tests are reached
+#![allow(dead_code)]
+
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
+#[path = "../../src/calibration.rs"]
+mod calibration;
+#[path = "../../src/command.rs"]
+mod command;
+#[path = "../../src/frame_buffer.rs"]
+mod frame_buffer;
+#[path = "../../src/frame_exchange.rs"]
+mod frame_exchange;
+#[path = "../../src/limits.rs"]
+mod limits;
+#[path = "../../src/mqtt_packet.rs"]
+mod mqtt_packet;
+#[path = "../../src/night_mode.rs"]
+mod night_mode;
+#[path = "../../src/stall_watchdog.rs"]
+mod stall_watchdog;
+#[path = "../../src/test_pattern.rs"]
+mod test_pattern;
@@ -2,10 +2,11 @@ use alloc::vec::Vec;
 
 use chrono::{DateTime, Timelike};
 use chrono::{Datelike, TimeZone};
+use chrono_tz::Tz;
 use libm::ceilf;
 
-
-use crate::picture::{Picture, StaticPartMeta};
+use crate::picture::{Picture, StaticPartMeta, FULL_INTENSITY};
+use crate::screensaver::Appearance;
 use crate::{font, ntptime};
 use bresenham::Point;
 
@@ -16,6 +17,12 @@ use sntpc::NtpTimestampGenerator;
 /// either 1 or 2
 pub const GLOBAL_SCALE: isize = 2;
 
+/// Brightness of the hour hand in percent. Brighter than the rest to stand out.
+const HOUR_HAND_BRIGHTNESS: u32 = 150;
+
+/// Intensity of the dots at the minute marks if an analog Z output exists
+const MINUTE_DOT_INTENSITY: u8 = 128;
+
 /// Converts radial coordinate int cartesian
 /// Provide phi in range of 0 to 2*PI
 /// Returns coordinates with Y+ going up assuming a vector scope coordinate system
@@ -82,27 +89,32 @@ fn draw_static_clock_face(pic: &mut Picture) {
                 pic.add_open_polygon(&line);
             }
         } else {
+            pic.set_intensity(MINUTE_DOT_INTENSITY);
             pic.add_dot2(outer, 14);
+            pic.set_intensity(FULL_INTENSITY);
         }
     }
 }
 
-fn draw_dynamic_parts(pic: &mut Picture) {
-    let local_time = critical_section::with(|cs| ntptime::PUBLIC_TIME.borrow(cs).get());
+/// Current local time, if already received via NTP
+pub fn local_time() -> Option<DateTime<Tz>> {
+    let mut local_time = critical_section::with(|cs| ntptime::PUBLIC_TIME.borrow(cs).get())?;
 
-    // Draw the hands if we have the time to present
-    if let Some(mut local_time) = local_time {
-        local_time.init();
-        let secs = local_time.timestamp_sec();
-        let nano = local_time.timestamp_subsec_micros() * 1000;
-
-        // Create Unix timestamp
-        let utc = DateTime::from_timestamp(secs as i64, nano).unwrap();
-        // Create a normal DateTime from the NaiveDateTime
-        // to get the local time.
-        // TODO At the moment limited to german time
-        let local_time = chrono_tz::Europe::Berlin.from_utc_datetime(&utc.naive_utc());
+    local_time.init();
+    let secs = local_time.timestamp_sec();
+    let nano = local_time.timestamp_subsec_micros() * 1000;
 
+    // Create Unix timestamp
+    let utc = DateTime::from_timestamp(secs as i64, nano).unwrap();
+    // Create a normal DateTime from the NaiveDateTime
+    // to get the local time.
+    // TODO At the moment limited to german time
+    Some(chrono_tz::Europe::Berlin.from_utc_datetime(&utc.naive_utc()))
+}
+
+fn draw_dynamic_parts(pic: &mut Picture, hands_only: bool) {
+    // Draw the hands if we have the time to present
+    if let Some(local_time) = local_time() {
         // Seconds - stalling
         /*
         let phi = (local_time.second() as f32 / 60.0) * core::f32::consts::PI * 2.0;
@@ -185,7 +197,13 @@ fn draw_dynamic_parts(pic: &mut Picture) {
             hours_phi + 0.3,
             (0x20 * GLOBAL_SCALE) as f32,
         ));
+        pic.set_brightness(HOUR_HAND_BRIGHTNESS);
         pic.add_closed_polygon(&hour_poly);
+        pic.set_brightness(100);
+
+        if hands_only {
+            return;
+        }
 
         // AM and PM
 
@@ -235,17 +253,24 @@ fn draw_dynamic_parts(pic: &mut Picture) {
             pic.draw_font(pictogram, scaler, translate_x, translate_y);
         }
     }
-
-    // bring the beam to a position to rest until the next picture
-    pic.add_raw_point(0, 0);
 }
 
-pub fn prepare_static_part(tx_buffer: &mut [u8]) -> StaticPartMeta {
+pub fn prepare_static_part(tx_buffer: &mut [u8], appearance: &Appearance) -> StaticPartMeta {
     let mut pic = Picture::new(tx_buffer);
+    pic.transform = appearance.transform;
+    pic.dim(appearance.dim_factor);
     draw_static_clock_face(&mut pic);
+    pic.frame.optimize_path(0);
+    if appearance.reversed {
+        pic.frame.reverse_order(0);
+    }
     return StaticPartMeta {
-        out_index: pic.out_index,
-        parts: pic.parts,
+        out_index: pic.frame.out_index,
+        parts: pic.frame.parts,
+        appearance: *appearance,
+        sample_rate: pic.sample_rate,
+        calibration: pic.calibration,
+        truncated: pic.frame.truncated,
     };
 }
 
@@ -254,8 +279,25 @@ pub fn draw_dynamic_part<'a, 'b>(
     static_part: &'b StaticPartMeta,
 ) -> Picture<'a> {
     let mut pic = Picture::new(tx_buffer);
-    pic.out_index = static_part.out_index;
-    pic.parts = static_part.parts.clone();
-    draw_dynamic_parts(&mut pic);
+    pic.frame.out_index = static_part.out_index;
+    pic.frame.parts = static_part.parts.clone();
+    pic.transform = static_part.appearance.transform;
+    pic.sample_rate = static_part.sample_rate;
+    pic.calibration = static_part.calibration;
+    pic.frame.truncated = static_part.truncated;
+    pic.dim(static_part.appearance.dim_factor);
+    draw_dynamic_parts(&mut pic, false);
+    // Only a few dynamic parts exist. Cheap enough to do it for every frame.
+    pic.frame.optimize_path(static_part.parts.len());
+    pic
+}
+
+/// Only the hands without the static clock face. Used at night.
+pub fn draw_minimal_face<'a>(tx_buffer: &'a mut [u8], appearance: &Appearance) -> Picture<'a> {
+    let mut pic = Picture::new(tx_buffer);
+    pic.transform = appearance.transform;
+    pic.dim(appearance.dim_factor);
+    draw_dynamic_parts(&mut pic, true);
+    pic.frame.optimize_path(0);
     pic
 }
@@ -0,0 +1,458 @@
+//! Adapts the XY output to the connected scope.
+//!
+//! The clock face is drawn for an ideal screen. The calibration moves, scales,
+//! mirrors, rotates and distorts the logical coordinates before they are
+//! converted to DAC samples. It is stored in flash by calibration_storage
+//! to survive a restart.
+//!
+//! Doesn't depend on the hardware, so it can be tested on the host.
+
+use core::cell::RefCell;
+use core::fmt;
+use core::ops::RangeInclusive;
+
+use critical_section::Mutex;
+use heapless::Vec;
+
+use libm::{cosf, roundf, sinf};
+
+use crate::limits::{self, MAX_COORDINATE};
+
+/// Center of the DAC range. Rotation and keystone are relative to it.
+const CENTER: f32 = MAX_COORDINATE / 2.0;
+
+/// Marks a stored calibration. Changed whenever the layout changes.
+const MAGIC: u32 = 0x5343_4c31;
+
+/// Bytes of a stored calibration
+pub const STORED_BYTES: usize = 36;
+
+const FLAG_SWAP_XY: u32 = 1 << 0;
+const FLAG_INVERT_X: u32 = 1 << 1;
+const FLAG_INVERT_Y: u32 = 1 << 2;
+
+/// Applied in this order: swap, inversion, gain, rotation, keystone, offset
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Calibration {
+    /// Shift in logical units
+    pub offset_x: f32,
+    pub offset_y: f32,
+    /// Factor of the size
+    pub gain_x: f32,
+    pub gain_y: f32,
+    /// Exchanges X and Y
+    pub swap_xy: bool,
+    /// Mirrors X at the center
+    pub invert_x: bool,
+    /// Mirrors Y at the center
+    pub invert_y: bool,
+    /// Counterclockwise in degrees
+    pub rotation: f32,
+    /// Change of the width per logical unit of Y, relative to the center.
+    /// Positive values make the top wider than the bottom.
+    pub keystone_x: f32,
+    /// Change of the height per logical unit of X, relative to the center.
+    /// Positive values make the right side higher than the left side.
+    pub keystone_y: f32,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError;
+
+/// Most settings accepted at once
+pub const MAX_SETTINGS: usize = 16;
+
+/// A single change of the calibration. The numbers are inside the limits.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Setting {
+    Reset,
+    OffsetX(f32),
+    OffsetY(f32),
+    GainX(f32),
+    GainY(f32),
+    SwapXy(bool),
+    InvertX(bool),
+    InvertY(bool),
+    Rotation(f32),
+    KeystoneX(f32),
+    KeystoneY(f32),
+}
+
+impl Setting {
+    /// Parses a setting like "gain_x=1.05", "invert_y=1" or "reset".
+    /// Numbers outside of the limits are rejected, including nan and infinity.
+    pub fn parse(setting: &str) -> Result<Setting, ParseError> {
+        if setting == "reset" {
+            return Ok(Setting::Reset);
+        }
+        let (key, value) = setting.split_once('=').ok_or(ParseError)?;
+        let number = |range: RangeInclusive<f32>| {
+            let number = value.parse::<f32>().map_err(|_| ParseError)?;
+            match range.contains(&number) {
+                true => Ok(number),
+                false => Err(ParseError),
+            }
+        };
+        let flag = || match value {
+            "1" | "true" => Ok(true),
+            "0" | "false" => Ok(false),
+            _ => Err(ParseError),
+        };
+        let setting = match key {
+            "offset_x" => Setting::OffsetX(number(limits::CALIBRATION_OFFSET)?),
+            "offset_y" => Setting::OffsetY(number(limits::CALIBRATION_OFFSET)?),
+            "gain_x" => Setting::GainX(number(limits::CALIBRATION_GAIN)?),
+            "gain_y" => Setting::GainY(number(limits::CALIBRATION_GAIN)?),
+            "swap_xy" => Setting::SwapXy(flag()?),
+            "invert_x" => Setting::InvertX(flag()?),
+            "invert_y" => Setting::InvertY(flag()?),
+            "rotation" => Setting::Rotation(number(limits::CALIBRATION_ROTATION)?),
+            "keystone_x" => Setting::KeystoneX(number(limits::CALIBRATION_KEYSTONE)?),
+            "keystone_y" => Setting::KeystoneY(number(limits::CALIBRATION_KEYSTONE)?),
+            _ => return Err(ParseError),
+        };
+        Ok(setting)
+    }
+}
+
+/// Parses settings separated by '&' or whitespace, e.g. "gain_x=1.1&rotation=-2".
+/// Fails if one of them is invalid.
+pub fn parse_settings(settings: &str) -> Result<Vec<Setting, MAX_SETTINGS>, ParseError> {
+    let mut parsed = Vec::new();
+    for setting in settings
+        .split(|c: char| c == '&' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+    {
+        parsed
+            .push(Setting::parse(setting)?)
+            .map_err(|_| ParseError)?;
+    }
+    Ok(parsed)
+}
+
+impl Calibration {
+    pub const IDENTITY: Calibration = Calibration {
+        offset_x: 0.0,
+        offset_y: 0.0,
+        gain_x: 1.0,
+        gain_y: 1.0,
+        swap_xy: false,
+        invert_x: false,
+        invert_y: false,
+        rotation: 0.0,
+        keystone_x: 0.0,
+        keystone_y: 0.0,
+    };
+
+    pub fn apply(&self, x: u16, y: u16) -> (u16, u16) {
+        if *self == Self::IDENTITY {
+            return (x, y);
+        }
+        let (mut x, mut y) = (x as f32 - CENTER, y as f32 - CENTER);
+        if self.swap_xy {
+            (x, y) = (y, x);
+        }
+        if self.invert_x {
+            x = -x;
+        }
+        if self.invert_y {
+            y = -y;
+        }
+        x *= self.gain_x;
+        y *= self.gain_y;
+
+        if self.rotation != 0.0 {
+            let phi = self.rotation.to_radians();
+            let (sin, cos) = (sinf(phi), cosf(phi));
+            (x, y) = (x * cos - y * sin, x * sin + y * cos);
+        }
+
+        let x_factor = 1.0 + self.keystone_x * y / CENTER;
+        let y_factor = 1.0 + self.keystone_y * x / CENTER;
+        let x = CENTER + x * x_factor + self.offset_x;
+        let y = CENTER + y * y_factor + self.offset_y;
+        (
+            roundf(x.clamp(0.0, MAX_COORDINATE)) as u16,
+            roundf(y.clamp(0.0, MAX_COORDINATE)) as u16,
+        )
+    }
+
+    /// Applies a setting which was already checked by Setting::parse
+    pub fn change(&mut self, setting: Setting) {
+        match setting {
+            Setting::Reset => *self = Self::IDENTITY,
+            Setting::OffsetX(v) => self.offset_x = v,
+            Setting::OffsetY(v) => self.offset_y = v,
+            Setting::GainX(v) => self.gain_x = v,
+            Setting::GainY(v) => self.gain_y = v,
+            Setting::SwapXy(v) => self.swap_xy = v,
+            Setting::InvertX(v) => self.invert_x = v,
+            Setting::InvertY(v) => self.invert_y = v,
+            Setting::Rotation(v) => self.rotation = v,
+            Setting::KeystoneX(v) => self.keystone_x = v,
+            Setting::KeystoneY(v) => self.keystone_y = v,
+        }
+    }
+
+    /// All numbers are inside the limits
+    fn is_valid(&self) -> bool {
+        [
+            (self.offset_x, limits::CALIBRATION_OFFSET),
+            (self.offset_y, limits::CALIBRATION_OFFSET),
+            (self.gain_x, limits::CALIBRATION_GAIN),
+            (self.gain_y, limits::CALIBRATION_GAIN),
+            (self.rotation, limits::CALIBRATION_ROTATION),
+            (self.keystone_x, limits::CALIBRATION_KEYSTONE),
+            (self.keystone_y, limits::CALIBRATION_KEYSTONE),
+        ]
+        .iter()
+        .all(|(number, range)| range.contains(number))
+    }
+
+    pub fn to_bytes(self) -> [u8; STORED_BYTES] {
+        let mut flags = 0;
+        for (set, flag) in [
+            (self.swap_xy, FLAG_SWAP_XY),
+            (self.invert_x, FLAG_INVERT_X),
+            (self.invert_y, FLAG_INVERT_Y),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+        let words = [
+            MAGIC,
+            flags,
+            self.offset_x.to_bits(),
+            self.offset_y.to_bits(),
+            self.gain_x.to_bits(),
+            self.gain_y.to_bits(),
+            self.rotation.to_bits(),
+            self.keystone_x.to_bits(),
+            self.keystone_y.to_bits(),
+        ];
+        let mut bytes = [0; STORED_BYTES];
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; STORED_BYTES]) -> Option<Calibration> {
+        let mut words = bytes
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        if words.next()? != MAGIC {
+            return None;
+        }
+        let flags = words.next()?;
+        let mut number = || words.next().map(f32::from_bits);
+        let calibration = Calibration {
+            offset_x: number()?,
+            offset_y: number()?,
+            gain_x: number()?,
+            gain_y: number()?,
+            rotation: number()?,
+            keystone_x: number()?,
+            keystone_y: number()?,
+            swap_xy: flags & FLAG_SWAP_XY != 0,
+            invert_x: flags & FLAG_INVERT_X != 0,
+            invert_y: flags & FLAG_INVERT_Y != 0,
+        };
+        // Erased flash or garbage must not distort the picture
+        calibration.is_valid().then_some(calibration)
+    }
+}
+
+/// One setting per line, in the format accepted by Setting::parse
+impl fmt::Display for Calibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "offset_x={}", self.offset_x)?;
+        writeln!(f, "offset_y={}", self.offset_y)?;
+        writeln!(f, "gain_x={}", self.gain_x)?;
+        writeln!(f, "gain_y={}", self.gain_y)?;
+        writeln!(f, "swap_xy={}", self.swap_xy as u8)?;
+        writeln!(f, "invert_x={}", self.invert_x as u8)?;
+        writeln!(f, "invert_y={}", self.invert_y as u8)?;
+        writeln!(f, "rotation={}", self.rotation)?;
+        writeln!(f, "keystone_x={}", self.keystone_x)?;
+        writeln!(f, "keystone_y={}", self.keystone_y)
+    }
+}
+
+static CALIBRATION: Mutex<RefCell<Calibration>> = Mutex::new(RefCell::new(Calibration::IDENTITY));
+
+/// Calibration used for the frames drawn next
+pub fn current() -> Calibration {
+    critical_section::with(|cs| *CALIBRATION.borrow_ref(cs))
+}
+
+/// Applies settings which were already checked by Setting::parse
+pub fn update(settings: &[Setting]) -> Calibration {
+    let mut calibration = current();
+    for setting in settings {
+        calibration.change(*setting);
+    }
+    set_current(calibration);
+    calibration
+}
+
+/// Replaces the calibration used for the frames drawn next
+pub fn set_current(calibration: Calibration) {
+    critical_section::with(|cs| *CALIBRATION.borrow_ref_mut(cs) = calibration);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(setting: &str) -> Result<Calibration, ParseError> {
+        let mut calibration = Calibration::IDENTITY;
+        calibration.change(Setting::parse(setting)?);
+        Ok(calibration)
+    }
+
+    #[test]
+    fn set_accepts_the_bounds() {
+        assert_eq!(set("gain_x=0.5").unwrap().gain_x, 0.5);
+        assert_eq!(set("gain_y=2").unwrap().gain_y, 2.0);
+        assert_eq!(set("offset_x=-255").unwrap().offset_x, -255.0);
+        assert_eq!(set("offset_y=255").unwrap().offset_y, 255.0);
+        assert_eq!(set("rotation=-180").unwrap().rotation, -180.0);
+        assert_eq!(set("keystone_x=0.5").unwrap().keystone_x, 0.5);
+        assert_eq!(set("keystone_y=-0.5").unwrap().keystone_y, -0.5);
+        assert!(set("swap_xy=1").unwrap().swap_xy);
+        assert!(set("invert_x=true").unwrap().invert_x);
+        assert!(!set("invert_y=0").unwrap().invert_y);
+    }
+
+    #[test]
+    fn set_rejects_invalid_settings() {
+        for setting in [
+            "gain_x=nan",
+            "gain_x=inf",
+            "gain_x=0",
+            "gain_y=-1",
+            "gain_y=2.01",
+            "offset_x=255.5",
+            "offset_y=-1e9",
+            "rotation=181",
+            "rotation=NaN",
+            "keystone_x=0.6",
+            "keystone_y=-inf",
+            "invert_x=2",
+            "swap_xy=yes",
+            "gain_x=",
+            "gain_x",
+            "x_gain=1",
+            "",
+        ] {
+            assert_eq!(set(setting), Err(ParseError), "{}", setting);
+        }
+    }
+
+    #[test]
+    fn reset_returns_to_the_identity() {
+        let mut calibration = set("rotation=10").unwrap();
+        calibration.change(Setting::parse("reset").unwrap());
+        assert_eq!(calibration, Calibration::IDENTITY);
+    }
+
+    #[test]
+    fn settings_are_parsed_completely() {
+        let settings = parse_settings("gain_x=1.1&rotation=-2\ninvert_y=1").unwrap();
+        assert_eq!(
+            settings.as_slice(),
+            [
+                Setting::GainX(1.1),
+                Setting::Rotation(-2.0),
+                Setting::InvertY(true)
+            ]
+        );
+        assert_eq!(parse_settings("gain_x=1.1&gain_y=0"), Err(ParseError));
+        let too_many = "reset&".repeat(MAX_SETTINGS + 1);
+        assert_eq!(parse_settings(&too_many), Err(ParseError));
+    }
+
+    #[test]
+    fn apply() {
+        let center = CENTER as u16;
+        assert_eq!(Calibration::IDENTITY.apply(12, 345), (12, 345));
+
+        let shifted = set("offset_x=10").unwrap();
+        assert_eq!(shifted.apply(100, 100), (110, 100));
+        // Kept inside the DAC range
+        assert_eq!(shifted.apply(505, 0), (510, 0));
+
+        let wide = set("gain_x=2").unwrap();
+        assert_eq!(
+            wide.apply(center + 10, center + 10),
+            (center + 20, center + 10)
+        );
+
+        let swapped = set("swap_xy=1").unwrap();
+        assert_eq!(swapped.apply(100, 200), (200, 100));
+
+        let inverted = set("invert_y=1").unwrap();
+        assert_eq!(inverted.apply(100, center + 50), (100, center - 50));
+
+        // Counterclockwise
+        let rotated = set("rotation=90").unwrap();
+        assert_eq!(rotated.apply(center + 50, center), (center, center + 50));
+
+        // The top gets wider
+        let keystone = set("keystone_x=0.5").unwrap();
+        assert_eq!(keystone.apply(center + 100, 510), (center + 150, 510));
+        assert_eq!(keystone.apply(center + 100, 0), (center + 50, 0));
+    }
+
+    #[test]
+    fn apply_keeps_the_smallest_gain_spread_out() {
+        let mut calibration = Calibration::IDENTITY;
+        for setting in parse_settings("gain_x=0.5&gain_y=0.5").unwrap() {
+            calibration.change(setting);
+        }
+        assert_eq!(calibration.apply(0, 0), (128, 128));
+        assert_eq!(calibration.apply(510, 510), (383, 383));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut calibration = Calibration::IDENTITY;
+        for setting in parse_settings(
+            "offset_x=-12.5 offset_y=3 gain_x=1.25 gain_y=0.75 swap_xy=1 invert_y=1 \
+             rotation=-2.5 keystone_x=0.01 keystone_y=-0.02",
+        )
+        .unwrap()
+        {
+            calibration.change(setting);
+        }
+        let bytes = calibration.to_bytes();
+        assert_eq!(Calibration::from_bytes(&bytes), Some(calibration));
+        let identity = Calibration::IDENTITY.to_bytes();
+        assert_eq!(
+            Calibration::from_bytes(&identity),
+            Some(Calibration::IDENTITY)
+        );
+    }
+
+    #[test]
+    fn from_bytes_rejects_invalid_contents() {
+        // Erased flash
+        assert_eq!(Calibration::from_bytes(&[0xff; STORED_BYTES]), None);
+
+        let word = |bytes: &mut [u8; STORED_BYTES], index: usize, value: u32| {
+            bytes[index * 4..index * 4 + 4].copy_from_slice(&value.to_le_bytes());
+        };
+        let identity = Calibration::IDENTITY.to_bytes();
+        // gain_x is the fifth word
+        for gain in [0.0, f32::NAN, f32::INFINITY, 3.0] {
+            let mut bytes = identity;
+            word(&mut bytes, 4, f32::to_bits(gain));
+            assert_eq!(Calibration::from_bytes(&bytes), None, "{}", gain);
+        }
+        let mut bytes = identity;
+        word(&mut bytes, 0, MAGIC + 1);
+        assert_eq!(Calibration::from_bytes(&bytes), None);
+    }
+}
@@ -0,0 +1,44 @@
+//! Keeps the calibration in flash to survive a restart.
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use embedded_storage::{ReadStorage, Storage};
+use esp_storage::FlashStorage;
+
+use crate::calibration::{self, Calibration, STORED_BYTES};
+
+/// Start of the nvs partition of the default partition table.
+/// Not used by anything else in this firmware.
+const FLASH_OFFSET: u32 = 0x9000;
+
+/// Calibration found in flash, None if unknown. Saving the same calibration
+/// again doesn't wear the flash.
+static STORED: Mutex<RefCell<Option<Calibration>>> = Mutex::new(RefCell::new(None));
+
+/// Restores the calibration from flash. Keeps the identity if nothing valid was stored.
+/// To be called before the display is started.
+pub fn load() {
+    let mut bytes = [0; STORED_BYTES];
+    if let Err(e) = FlashStorage::new().read(FLASH_OFFSET, &mut bytes) {
+        esp_println::println!("Reading calibration failed: {:?}", e);
+        return;
+    }
+    if let Some(stored) = Calibration::from_bytes(&bytes) {
+        calibration::set_current(stored);
+        critical_section::with(|cs| *STORED.borrow_ref_mut(cs) = Some(stored));
+    }
+}
+
+/// Stores the current calibration in flash unless it is already stored.
+/// The flash cache is disabled while writing, so the display has to be paused.
+pub fn save() -> Result<(), esp_storage::FlashStorageError> {
+    let current = calibration::current();
+    if critical_section::with(|cs| *STORED.borrow_ref(cs)) == Some(current) {
+        return Ok(());
+    }
+    let bytes = current.to_bytes();
+    crate::scopeclock::pause_display(|| FlashStorage::new().write(FLASH_OFFSET, &bytes))?;
+    critical_section::with(|cs| *STORED.borrow_ref_mut(cs) = Some(current));
+    Ok(())
+}
@@ -0,0 +1,242 @@
+//! Continuous output of frames with a circular chain of DMA descriptors.
+//!
+//! Every frame buffer has its own chain of descriptors. The last descriptor of a
+//! chain points back to the first one, so the DMA repeats the frame on its own.
+//! To show the next frame, the last descriptor of the running chain is pointed to
+//! the first descriptor of the next chain. The DMA follows at the end of the frame.
+//! No interrupt is involved.
+//!
+//! The beam can't be blanked between the parts as there is no interrupt to
+//! toggle the Z blanking. The idle time at the end of a frame is covered by
+//! the samples parked at the rest position which Picture adds anyway.
+//! The jumps between the parts would be drawn with the beam on, so the backend
+//! has to carry the blanking in the samples. The feature therefore enables mcp4922-z.
+
+#[path = "util.rs"]
+mod examples_util;
+
+use examples_util::hal;
+use hal::peripherals::I2S0;
+use hal::prelude::*;
+
+use crate::display_backend::{ActiveBackend, DisplayBackend};
+
+/// Both I2S units share the same register layout
+type I2sRegisters = <I2S0 as core::ops::Deref>::Target;
+
+/// Bytes of a sample. The next frame starts at a descriptor boundary,
+/// which must not split a sample.
+const SAMPLE_BYTES: usize = ActiveBackend::SAMPLE_BYTES;
+
+/// Largest amount of bytes a single descriptor can cover, rounded down to whole samples
+const MAX_DESCRIPTOR_BYTES: usize = 4095 / SAMPLE_BYTES * SAMPLE_BYTES;
+
+/// The buffer of a descriptor is owned by the DMA
+const OWNER_DMA: u32 = 1 << 31;
+
+/// Linked list item as read by the DMA of the ESP32
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Descriptor {
+    /// Size and length of the buffer and the owner
+    flags: u32,
+    buffer: u32,
+    next: u32,
+}
+
+impl Descriptor {
+    pub const EMPTY: Descriptor = Descriptor {
+        flags: 0,
+        buffer: 0,
+        next: 0,
+    };
+}
+
+/// Amount of descriptors required for a frame buffer of the given size
+pub const fn chain_length(buffer_bytes: usize) -> usize {
+    buffer_bytes.div_ceil(MAX_DESCRIPTOR_BYTES)
+}
+
+/// Descriptors of a single frame buffer
+struct FrameChain {
+    descriptors: &'static mut [Descriptor],
+    /// Address of the frame buffer covered by this chain
+    buffer: usize,
+    /// Descriptors in use by the current frame
+    used: usize,
+}
+
+impl FrameChain {
+    fn first(&self) -> u32 {
+        self.descriptors.as_ptr() as u32
+    }
+
+    /// Covers the samples with descriptors. The last one points back to the first.
+    /// At least 2 descriptors are used, so the DMA visibly moves on even for short frames.
+    fn prepare(&mut self, samples: &[u8]) {
+        let first = self.first();
+        let chunk_bytes = (samples.len() / 2)
+            .next_multiple_of(SAMPLE_BYTES)
+            .clamp(SAMPLE_BYTES, MAX_DESCRIPTOR_BYTES);
+        let chunks = samples.chunks(chunk_bytes);
+        self.used = chunks.len();
+        assert!(self.used <= self.descriptors.len(), "Frame too long");
+
+        for (index, chunk) in chunks.enumerate() {
+            let next = match index + 1 {
+                next if next == self.used => first,
+                next => &self.descriptors[next] as *const Descriptor as u32,
+            };
+            let length = chunk.len() as u32;
+            self.descriptors[index] = Descriptor {
+                flags: OWNER_DMA | (length << 12) | length,
+                buffer: chunk.as_ptr() as u32,
+                next,
+            };
+        }
+    }
+
+    /// Lets the DMA continue with the other chain after the end of this one.
+    /// A single word is written, so the DMA sees either the old or the new link.
+    fn link_to(&mut self, first_of_other: u32) {
+        let last = &mut self.descriptors[self.used - 1];
+        // SAFETY: The descriptor is read by the DMA concurrently
+        unsafe { core::ptr::write_volatile(&mut last.next, first_of_other) };
+    }
+
+    fn contains(&self, descriptor: u32) -> bool {
+        let start = self.first();
+        let end = start + (self.used * core::mem::size_of::<Descriptor>()) as u32;
+        (start..end).contains(&descriptor)
+    }
+}
+
+/// Keeps the I2S busy with one frame after the other
+pub struct CircularDma {
+    registers: &'static I2sRegisters,
+    chains: [FrameChain; 2],
+    /// Index of the chain the DMA is working on
+    active: usize,
+    /// Index of the chain the DMA is about to switch to
+    queued: Option<usize>,
+    last_descriptor: u32,
+}
+
+// SAFETY: Only used from inside the critical section guarding the display driver
+unsafe impl Send for CircularDma {}
+
+impl CircularDma {
+    /// Requires the configured backend as the I2S must be set up already.
+    /// The buffers must be the frame buffers which are shown later.
+    pub fn new<B: DisplayBackend>(
+        _backend: &B,
+        descriptors: [&'static mut [Descriptor]; 2],
+        buffers: [&[u8]; 2],
+    ) -> Self {
+        let [descriptors0, descriptors1] = descriptors;
+        let chain = |descriptors, buffer: &[u8]| FrameChain {
+            descriptors,
+            buffer: buffer.as_ptr() as usize,
+            used: 0,
+        };
+        Self {
+            // SAFETY: The registers are memory mapped and exist for the whole runtime.
+            // The I2S driver isn't used to transfer anything in circular mode.
+            registers: unsafe { &*(B::I2S_BASE as *const I2sRegisters) },
+            chains: [
+                chain(descriptors0, buffers[0]),
+                chain(descriptors1, buffers[1]),
+            ],
+            active: 0,
+            queued: None,
+            last_descriptor: 0,
+        }
+    }
+
+    fn chain_index(&self, samples: &[u8]) -> usize {
+        let address = samples.as_ptr() as usize;
+        self.chains
+            .iter()
+            .position(|chain| chain.buffer == address)
+            .expect("Unknown frame buffer")
+    }
+
+    /// Starts to repeat the first frame
+    pub fn start(&mut self, samples: &[u8]) {
+        self.active = self.chain_index(samples);
+        let chain = &mut self.chains[self.active];
+        chain.prepare(samples);
+        let first = chain.first();
+
+        let registers = self.registers;
+        registers.conf().modify(|_, w| w.tx_reset().set_bit());
+        registers.conf().modify(|_, w| w.tx_reset().clear_bit());
+        registers
+            .lc_conf()
+            .modify(|_, w| w.out_rst().set_bit().ahbm_rst().set_bit());
+        registers
+            .lc_conf()
+            .modify(|_, w| w.out_rst().clear_bit().ahbm_rst().clear_bit());
+        // The owner bit is never handed back, as the descriptors are used over and over
+        registers.lc_conf().modify(|_, w| {
+            w.out_auto_wrback()
+                .clear_bit()
+                .check_owner()
+                .clear_bit()
+                .outdscr_burst_en()
+                .set_bit()
+                .out_data_burst_en()
+                .set_bit()
+        });
+        registers.fifo_conf().modify(|_, w| w.dscr_en().set_bit());
+        registers
+            .out_link()
+            .modify(|_, w| unsafe { w.outlink_addr().bits(first & 0xfffff) });
+        registers
+            .out_link()
+            .modify(|_, w| w.outlink_start().set_bit());
+        registers.conf().modify(|_, w| w.tx_start().set_bit());
+    }
+
+    /// Lets the DMA switch to the given frame after finishing the current one.
+    /// The frame must not be touched until switched_to_queued() returns true.
+    #[ram]
+    pub fn queue(&mut self, samples: &[u8]) {
+        let index = self.chain_index(samples);
+        assert!(index != self.active, "Frame is still shown");
+        self.chains[index].prepare(samples);
+        let first = self.chains[index].first();
+        self.chains[self.active].link_to(first);
+        self.queued = Some(index);
+    }
+
+    /// True once if the DMA has started with the queued frame.
+    /// The frame shown before is free afterwards.
+    #[ram]
+    pub fn switched_to_queued(&mut self) -> bool {
+        let Some(queued) = self.queued else {
+            return false;
+        };
+        if !self.chains[queued].contains(self.current_descriptor()) {
+            return false;
+        }
+        self.active = queued;
+        self.queued = None;
+        true
+    }
+
+    /// True if the DMA moved on to another descriptor since the last call.
+    /// Shows that the output is still running.
+    #[ram]
+    pub fn made_progress(&mut self) -> bool {
+        let descriptor = self.current_descriptor();
+        let progress = descriptor != self.last_descriptor;
+        self.last_descriptor = descriptor;
+        progress
+    }
+
+    /// Address of the descriptor the DMA is working on
+    fn current_descriptor(&self) -> u32 {
+        self.registers.out_link_dscr().read().bits()
+    }
+}
@@ -0,0 +1,353 @@
+//! Settings which can be changed over the network.
+//!
+//! MQTT and HTTP use the same names and the same validation. A command is
+//! parsed and checked completely before anything is changed, so an invalid
+//! payload is rejected with an error instead of being applied partially.
+//!
+//! Only the parsing is done here, independent of the hardware.
+//! The parsed commands are applied by the settings module.
+
+use core::fmt;
+use core::ops::RangeInclusive;
+
+use heapless::Vec;
+
+use crate::calibration::{self, Setting};
+use crate::limits;
+use crate::night_mode::DisplayMode;
+use crate::test_pattern::TestPattern;
+
+#[derive(Debug)]
+pub enum Command<'a> {
+    /// Busy loops before the blanking at the end of a part
+    BeamOff(u32),
+    /// Nanoseconds the beam rests blanked on the first sample of a part
+    BeamOn(u32),
+    BeamSpeed(u32),
+    /// In percent
+    Brightness(u32),
+    /// In Hz
+    SampleRate(u32),
+    CornerDwell(u32),
+    CornerOvershoot(u32),
+    /// In Hz. 0 disables the padding of frames.
+    FrameRate(u32),
+    Screensaver(bool),
+    OrbitRadius(u32),
+    /// Rules separated by ';' as accepted by night_mode::set_rules
+    NightRules(&'a str),
+    /// None returns to the night rules
+    DisplayMode(Option<DisplayMode>),
+    /// Changes of the calibration, applied in this order
+    Calibration(Vec<Setting, { calibration::MAX_SETTINGS }>),
+    /// None returns to the clock
+    TestPattern(Option<TestPattern>),
+    CalibrationSave,
+    /// Shown instead of the clock face. Empty to remove the message.
+    Message(&'a str),
+    /// Seconds a message is shown
+    MessageTimeout(u32),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    UnknownCommand,
+    /// The payload is not valid UTF-8
+    NotText,
+    NotANumber,
+    OutOfRange(RangeInclusive<u32>),
+    /// The text is longer than the given amount of bytes
+    TooLong(usize),
+    /// The text couldn't be parsed
+    Invalid,
+    /// The command was valid but couldn't be executed
+    Failed,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownCommand => write!(f, "unknown command"),
+            Error::NotText => write!(f, "payload is not text"),
+            Error::NotANumber => write!(f, "not a number"),
+            Error::OutOfRange(range) => {
+                write!(f, "out of range {}..={}", range.start(), range.end())
+            }
+            Error::TooLong(max) => write!(f, "longer than {} bytes", max),
+            Error::Invalid => write!(f, "invalid value"),
+            Error::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+fn number(value: &str, range: RangeInclusive<u32>) -> Result<u32, Error> {
+    let number = value.parse::<u32>().map_err(|_| Error::NotANumber)?;
+    match range.contains(&number) {
+        true => Ok(number),
+        false => Err(Error::OutOfRange(range)),
+    }
+}
+
+impl<'a> Command<'a> {
+    /// Creates the command with the given name from its payload
+    pub fn parse(name: &str, payload: &'a [u8]) -> Result<Command<'a>, Error> {
+        let value = core::str::from_utf8(payload)
+            .map_err(|_| Error::NotText)?
+            .trim();
+        let command = match name {
+            "beam_off" => Command::BeamOff(number(value, limits::WAIT_BEFORE_BEAM_OFF)?),
+            "beam_on" => Command::BeamOn(number(value, limits::WAIT_AFTER_BEAM_ON)?),
+            "beam_speed" => Command::BeamSpeed(number(value, limits::BEAM_SPEED)?),
+            "brightness" => Command::Brightness(number(value, limits::BRIGHTNESS)?),
+            "sample_rate" => Command::SampleRate(number(value, limits::SAMPLE_RATE)?),
+            "corner_dwell" => Command::CornerDwell(number(value, limits::CORNER_DWELL)?),
+            "corner_overshoot" => {
+                Command::CornerOvershoot(number(value, limits::CORNER_OVERSHOOT)?)
+            }
+            "frame_rate" if value == "0" => Command::FrameRate(0),
+            "frame_rate" => Command::FrameRate(number(value, limits::FRAME_RATE)?),
+            "screensaver" => Command::Screensaver(number(value, 0..=1)? != 0),
+            "orbit_radius" => Command::OrbitRadius(number(value, limits::ORBIT_RADIUS)?),
+            "night_rules" => Command::NightRules(value),
+            "display_mode" => Command::DisplayMode(match value {
+                "auto" => None,
+                name => Some(DisplayMode::parse(name).ok_or(Error::Invalid)?),
+            }),
+            "calibration" => Command::Calibration(
+                calibration::parse_settings(value).map_err(|_| Error::Invalid)?,
+            ),
+            "test_pattern" => Command::TestPattern(match value {
+                "none" => None,
+                name => Some(TestPattern::parse(name).ok_or(Error::Invalid)?),
+            }),
+            "calibration_save" => Command::CalibrationSave,
+            "message" if value.len() > limits::MESSAGE_LENGTH => {
+                return Err(Error::TooLong(limits::MESSAGE_LENGTH))
+            }
+            "message" => Command::Message(value),
+            "message_timeout" => Command::MessageTimeout(number(value, limits::MESSAGE_TIMEOUT)?),
+            _ => return Err(Error::UnknownCommand),
+        };
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::string::ToString;
+    use std::vec;
+
+    /// Numeric commands with their bounds
+    fn numbers() -> [(&'static str, RangeInclusive<u32>); 11] {
+        [
+            ("beam_off", limits::WAIT_BEFORE_BEAM_OFF),
+            ("beam_on", limits::WAIT_AFTER_BEAM_ON),
+            ("beam_speed", limits::BEAM_SPEED),
+            ("brightness", limits::BRIGHTNESS),
+            ("sample_rate", limits::SAMPLE_RATE),
+            ("corner_dwell", limits::CORNER_DWELL),
+            ("corner_overshoot", limits::CORNER_OVERSHOOT),
+            ("frame_rate", limits::FRAME_RATE),
+            ("screensaver", 0..=1),
+            ("orbit_radius", limits::ORBIT_RADIUS),
+            ("message_timeout", limits::MESSAGE_TIMEOUT),
+        ]
+    }
+
+    const TEXTS: [&str; 6] = [
+        "night_rules",
+        "display_mode",
+        "calibration",
+        "test_pattern",
+        "calibration_save",
+        "message",
+    ];
+
+    fn value(command: Command) -> u32 {
+        match command {
+            Command::BeamOff(v)
+            | Command::BeamOn(v)
+            | Command::BeamSpeed(v)
+            | Command::Brightness(v)
+            | Command::SampleRate(v)
+            | Command::CornerDwell(v)
+            | Command::CornerOvershoot(v)
+            | Command::FrameRate(v)
+            | Command::OrbitRadius(v)
+            | Command::MessageTimeout(v) => v,
+            Command::Screensaver(v) => v as u32,
+            other => panic!("{:?} is not numeric", other),
+        }
+    }
+
+    fn parse(name: &str, payload: &str) -> Result<u32, Error> {
+        Command::parse(name, payload.as_bytes()).map(value)
+    }
+
+    #[test]
+    fn numbers_accept_their_bounds() {
+        for (name, range) in numbers() {
+            for number in [*range.start(), *range.end()] {
+                let payload = number.to_string();
+                assert_eq!(parse(name, &payload), Ok(number), "{} {}", name, payload);
+            }
+        }
+        assert_eq!(parse("brightness", " 150\n"), Ok(150));
+    }
+
+    #[test]
+    fn numbers_reject_values_out_of_range() {
+        for (name, range) in numbers() {
+            let mut outside = vec![range.end() + 1];
+            if *range.start() > 0 {
+                outside.push(range.start() - 1);
+            }
+            for number in outside {
+                let payload = number.to_string();
+                assert_eq!(
+                    parse(name, &payload),
+                    Err(Error::OutOfRange(range.clone())),
+                    "{} {}",
+                    name,
+                    payload
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn numbers_reject_garbage() {
+        for (name, _) in numbers() {
+            for payload in ["", "abc", "-1", "1.5", "0x10", "99999999999"] {
+                assert_eq!(
+                    parse(name, payload),
+                    Err(Error::NotANumber),
+                    "{} {}",
+                    name,
+                    payload
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn frame_rate_zero_disables_the_padding() {
+        assert_eq!(parse("frame_rate", "0"), Ok(0));
+        assert_eq!(
+            parse("frame_rate", "1"),
+            Err(Error::OutOfRange(limits::FRAME_RATE))
+        );
+    }
+
+    #[test]
+    fn every_command_rejects_invalid_utf8() {
+        let names = numbers().map(|(name, _)| name);
+        for name in names.iter().chain(TEXTS.iter()) {
+            let result = Command::parse(name, &[b'1', 0xff, 0xfe]);
+            assert!(matches!(result, Err(Error::NotText)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn display_mode() {
+        let parse = |payload: &'static str| Command::parse("display_mode", payload.as_bytes());
+        assert!(matches!(parse("auto"), Ok(Command::DisplayMode(None))));
+        assert!(matches!(
+            parse("minimal"),
+            Ok(Command::DisplayMode(Some(DisplayMode::Minimal)))
+        ));
+        assert!(matches!(parse("bright"), Err(Error::Invalid)));
+        assert!(matches!(parse(""), Err(Error::Invalid)));
+    }
+
+    #[test]
+    fn test_pattern() {
+        let parse = |payload: &'static str| Command::parse("test_pattern", payload.as_bytes());
+        assert!(matches!(parse("none"), Ok(Command::TestPattern(None))));
+        assert!(matches!(
+            parse("blank_timing"),
+            Ok(Command::TestPattern(Some(TestPattern::BlankTiming)))
+        ));
+        assert!(matches!(parse("Crosshatch"), Err(Error::Invalid)));
+        assert!(matches!(parse(""), Err(Error::Invalid)));
+    }
+
+    #[test]
+    fn calibration() {
+        let parse = |payload: &'static str| Command::parse("calibration", payload.as_bytes());
+        let Ok(Command::Calibration(settings)) = parse(" gain_x=1.1&rotation=-2 invert_y=1\n")
+        else {
+            panic!("calibration not parsed");
+        };
+        assert_eq!(
+            settings.as_slice(),
+            [
+                Setting::GainX(1.1),
+                Setting::Rotation(-2.0),
+                Setting::InvertY(true)
+            ]
+        );
+        assert!(matches!(parse("reset"), Ok(Command::Calibration(s)) if s == [Setting::Reset]));
+        for payload in [
+            "gain_x=nan",
+            "gain_x=0",
+            "gain_y=2.5",
+            "offset_x=1e6",
+            "rotation=inf",
+            "keystone_y=1",
+            "x_gain=1.0",
+            "gain_x",
+            "gain_x=1.1&swap_xy",
+            "invert_x=2",
+        ] {
+            assert!(matches!(parse(payload), Err(Error::Invalid)), "{}", payload);
+        }
+    }
+
+    #[test]
+    fn message() {
+        let longest = "x".repeat(limits::MESSAGE_LENGTH);
+        let result = Command::parse("message", longest.as_bytes());
+        assert!(matches!(result, Ok(Command::Message(text)) if text == longest));
+        let too_long = "x".repeat(limits::MESSAGE_LENGTH + 1);
+        let result = Command::parse("message", too_long.as_bytes());
+        assert!(matches!(
+            result,
+            Err(Error::TooLong(limits::MESSAGE_LENGTH))
+        ));
+        let result = Command::parse("message", b" Hello \n");
+        assert!(matches!(result, Ok(Command::Message("Hello"))));
+        assert!(matches!(
+            Command::parse("message", b""),
+            Ok(Command::Message(""))
+        ));
+    }
+
+    #[test]
+    fn texts_checked_on_execution_are_passed_trimmed() {
+        let result = Command::parse("night_rules", b" mon-fri 22:00-06:00 dim \n");
+        assert!(matches!(
+            result,
+            Ok(Command::NightRules("mon-fri 22:00-06:00 dim"))
+        ));
+        let result = Command::parse("calibration_save", b"");
+        assert!(matches!(result, Ok(Command::CalibrationSave)));
+    }
+
+    #[test]
+    fn unknown_commands_are_rejected() {
+        for name in ["", "Brightness", "beam", "brightness/", "set/brightness"] {
+            let result = Command::parse(name, b"100");
+            assert!(matches!(result, Err(Error::UnknownCommand)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn errors_name_the_range() {
+        assert_eq!(
+            Error::OutOfRange(limits::BRIGHTNESS).to_string(),
+            "out of range 10..=400"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+#[path = "util.rs"]
+mod examples_util;
+
+use examples_util::hal;
+use hal::clock::Clocks;
+use hal::delay::Delay;
+use hal::peripherals::{Interrupt, I2S0};
+
+use crate::limits;
+
+#[cfg(not(feature = "mcp4922"))]
+pub type ActiveBackend = crate::internal_dac::InternalDac;
+
+#[cfg(feature = "mcp4922")]
+pub type ActiveBackend = crate::mcp4922::Mcp4922;
+
+/// Everything which depends on the DAC used to create the XY signal.
+///
+/// All backends use an I2S unit with DMA as this is the only way on the ESP32
+/// to stream samples with a constant rate. The Z blanking on GPIO 32 is
+/// independent of the backend.
+pub trait DisplayBackend: Send + Sized {
+    /// Peripherals and pins required by the backend
+    type Resources;
+
+    /// Bits per coordinate. Picture stores the samples with this resolution.
+    const RESOLUTION_BITS: u32;
+    /// Bytes of a single sample in the frame buffer
+    const SAMPLE_BYTES: usize;
+    /// Samples per second after start. Also the reference for the beam speed.
+    const SAMPLE_RATE: u32;
+    /// Interrupt source of the I2S unit. Handled by the NMI in high_level.S
+    const INTERRUPT: Interrupt;
+    /// Base address of the registers of the I2S unit
+    const I2S_BASE: u32;
+    /// True if every sample carries the blanking of the beam.
+    /// Frames are then transferred at once instead of part by part,
+    /// but the intensity of the parts is ignored.
+    const BLANK_IN_SAMPLE: bool;
+
+    /// Configures the hardware. The descriptors must cover the largest transfer.
+    fn new(
+        resources: Self::Resources,
+        tx_descriptors: &'static mut [u32],
+        rx_descriptors: &'static mut [u32],
+        clocks: &Clocks,
+    ) -> Self;
+
+    /// Stores a sample with coordinates of the native resolution.
+    /// The state of the beam is ignored if the blanking isn't part of the samples.
+    fn encode(x: u16, y: u16, beam_on: bool, sample: &mut [u8]);
+    /// Reads back the coordinates of a sample
+    fn decode(sample: &[u8]) -> (u16, u16);
+
+    /// Transfers the samples and waits until the DMA is done
+    fn write_blocking(&mut self, samples: &[u8], delay: &Delay);
+    /// Starts transferring the samples without waiting.
+    /// The end is signaled by the tx_rempty interrupt.
+    ///
+    /// # Safety
+    ///
+    /// The DMA keeps reading the samples after the call returns, but the borrow ends.
+    /// The caller must leave the samples in place and unchanged until tx_rempty signals
+    /// the end, and must not start another transfer before.
+    unsafe fn start_write(&mut self, samples: &[u8]);
+
+    /// Changes the samples per second. Takes effect immediately, so it should
+    /// be called between frames. The rate is kept if it can't be reached.
+    fn set_sample_rate(&mut self, rate: u32) -> Result<(), UnsupportedRate>;
+
+    fn clear_tx_rempty(&self);
+    fn enable_tx_rempty(&self);
+}
+
+/// Offset of I2S_INT_ENA_REG from the base address of an I2S unit
+pub const I2S_INT_ENA_OFFSET: u32 = 0x14;
+/// Offset of I2S_INT_CLR_REG from the base address of an I2S unit
+pub const I2S_INT_CLR_OFFSET: u32 = 0x18;
+
+/// Both I2S units share the same register layout
+type I2sRegisters = <I2S0 as core::ops::Deref>::Target;
+
+/// Access to the interrupt registers of the I2S which are not covered by the I2S driver
+pub struct I2sInterrupts(&'static I2sRegisters);
+
+// SAFETY: Only used from inside the critical section guarding the display driver
+unsafe impl Send for I2sInterrupts {}
+
+impl I2sInterrupts {
+    /// Requires the peripheral to make sure it is still owned and not handed to someone else
+    pub fn new<P: core::ops::Deref<Target = I2sRegisters>>(i2s: &P) -> Self {
+        let registers: *const I2sRegisters = &**i2s;
+        // SAFETY: The registers are memory mapped and exist for the whole runtime.
+        // Only the interrupt registers are accessed, which the I2S driver leaves alone.
+        Self(unsafe { &*registers })
+    }
+
+    pub fn clear_tx_rempty(&self) {
+        self.0.int_clr().write(|f| f.tx_rempty().clear_bit_by_one());
+    }
+
+    pub fn enable_tx_rempty(&self) {
+        self.0.int_ena().write(|f| f.tx_rempty().set_bit());
+    }
+}
+
+/// Largest denominator of the fractional clock divider
+const CLKM_DIV_A_MAX: u32 = 63;
+
+/// The rate requires a divider outside of limits::CLOCK_DIVIDER
+#[derive(Debug)]
+pub struct UnsupportedRate;
+
+/// Changes the clock of an I2S unit after the I2S driver has configured it.
+///
+/// The driver only supports a fixed rate. Its divider for the reference rate is
+/// captured and scaled for other rates, so the remaining clock setup is kept.
+pub struct I2sClock {
+    registers: &'static I2sRegisters,
+    /// MCLK divider of the reference rate including the fractional part
+    reference_divider: f32,
+    reference_rate: u32,
+}
+
+// SAFETY: Only used from inside the critical section guarding the display driver
+unsafe impl Send for I2sClock {}
+
+impl I2sClock {
+    /// To be called after the I2S driver has configured the clock for the reference rate.
+    /// The unit of the rate doesn't matter as long as set_rate() uses the same.
+    pub fn capture(i2s_base: u32, reference_rate: u32) -> Self {
+        // SAFETY: The registers are memory mapped and exist for the whole runtime.
+        // The I2S driver doesn't touch the clock after the configuration.
+        let registers: &'static I2sRegisters = unsafe { &*(i2s_base as *const I2sRegisters) };
+        let conf = registers.clkm_conf().read();
+        let (num, b, a) = (
+            conf.clkm_div_num().bits() as f32,
+            conf.clkm_div_b().bits() as f32,
+            conf.clkm_div_a().bits() as f32,
+        );
+        let fraction = if a > 0.0 { b / a } else { 0.0 };
+        Self {
+            registers,
+            reference_divider: num + fraction,
+            reference_rate,
+        }
+    }
+
+    /// Approximates the rate with the divider N + b/a
+    pub fn set_rate(&mut self, rate: u32) -> Result<(), UnsupportedRate> {
+        let divider = self.reference_divider * self.reference_rate as f32 / rate as f32;
+        let mut num = divider as u32;
+        let fraction = divider - num as f32;
+
+        let mut best = (0, 1, fraction);
+        for a in 1..=CLKM_DIV_A_MAX {
+            let b = libm::roundf(fraction * a as f32) as u32;
+            let error = libm::fabsf(fraction - b as f32 / a as f32);
+            if error < best.2 {
+                best = (b, a, error);
+            }
+        }
+        let (mut b, a, _) = best;
+        if b == a {
+            num += 1;
+            b = 0;
+        }
+        if !limits::CLOCK_DIVIDER.contains(&num) {
+            return Err(UnsupportedRate);
+        }
+
+        self.registers.clkm_conf().modify(|_, w| unsafe {
+            w.clkm_div_num()
+                .bits(num as u8)
+                .clkm_div_b()
+                .bits(b as u8)
+                .clkm_div_a()
+                .bits(a as u8)
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,635 @@
+//! Samples of a frame and the parts they form.
+//!
+//! A part is a run of samples which is drawn with the beam on. The parts are
+//! reordered and chained here to shorten the blanked travel of the beam.
+//! Doesn't depend on the hardware. The layout of the samples is provided by
+//! a SampleFormat, so the buffer handling can be tested on the host.
+
+use core::marker::PhantomData;
+
+use alloc::vec::Vec;
+
+/// Start and end byte index of a part in the frame buffer and the intensity of the beam
+pub type Part = (usize, usize, u8);
+
+/// Intensity of the beam if nothing else is requested
+pub const FULL_INTENSITY: u8 = 255;
+
+/// Position of the beam between two frames
+pub const REST_POSITION: (u16, u16) = (0, 0);
+
+/// Blanked samples at the start and the end of every part if the blanking is part of
+/// the samples. Gives the amplifiers time to settle before and after the beam is on.
+const SETTLE_SAMPLES: usize = 4;
+
+/// Samples at the end of the frame buffer which are not used for drawing. They leave room
+/// for the blanked samples of finish_part and at least one idle sample of finish_frame.
+pub const RESERVED_SAMPLES: usize = 2 * SETTLE_SAMPLES + 1;
+
+/// How the display backend stores a sample in the frame buffer
+pub trait SampleFormat {
+    /// Bits of the coordinates
+    const RESOLUTION_BITS: u32;
+    const SAMPLE_BYTES: usize;
+    /// True if the blanking is part of every sample. A frame is then transferred at once.
+    const BLANK_IN_SAMPLE: bool;
+
+    fn encode(x: u16, y: u16, beam_on: bool, sample: &mut [u8]);
+    fn decode(sample: &[u8]) -> (u16, u16);
+}
+
+fn distance_squared(a: (u16, u16), b: (u16, u16)) -> u32 {
+    let dx = a.0 as i32 - b.0 as i32;
+    let dy = a.1 as i32 - b.1 as i32;
+    (dx * dx + dy * dy) as u32
+}
+
+pub struct FrameBuffer<'a, F: SampleFormat> {
+    pub tx_buffer: &'a mut [u8],
+    pub out_index: usize,
+    pub parts: Vec<Part>,
+    /// Blanked samples at the end of the frame to park the beam
+    pub idle: (usize, usize),
+    /// Set if samples were dropped as the frame buffer was full
+    pub truncated: bool,
+    format: PhantomData<F>,
+}
+
+impl<'a, F: SampleFormat> FrameBuffer<'a, F> {
+    /// Parts which are closer than 2 steps of an 8 bit DAC are drawn without blanking
+    /// in between. Saves the interrupt and the blank for every connection.
+    const MERGE_DISTANCE_SQUARED: u32 = {
+        let distance = 2 << (F::RESOLUTION_BITS - 8);
+        distance * distance
+    };
+
+    const SETTLE_BYTES: usize = SETTLE_SAMPLES * F::SAMPLE_BYTES;
+
+    pub fn new(tx_buffer: &'a mut [u8]) -> FrameBuffer<'a, F> {
+        Self {
+            tx_buffer,
+            out_index: 0,
+            parts: Vec::new(),
+            idle: (0, 0),
+            truncated: false,
+            format: PhantomData,
+        }
+    }
+
+    /// Amount of samples stored in this frame.
+    /// As samples are output with a fixed rate, this is a measure for the time
+    /// required to draw it.
+    pub fn samples(&self) -> usize {
+        self.out_index / F::SAMPLE_BYTES
+    }
+
+    /// Adds a sample with coordinates in the resolution of the DAC.
+    /// Dropped if only the reserved samples at the end of the buffer are left.
+    pub fn add_native_point(&mut self, x: u16, y: u16) {
+        let limit = self
+            .tx_buffer
+            .len()
+            .saturating_sub(RESERVED_SAMPLES * F::SAMPLE_BYTES);
+        if self.out_index + F::SAMPLE_BYTES > limit {
+            self.truncated = true;
+            return;
+        }
+        self.add_sample(x, y, true);
+    }
+
+    /// Stores a sample at the end of the frame. Dropped if the buffer is full.
+    fn add_sample(&mut self, x: u16, y: u16, beam_on: bool) {
+        let Some(sample) = self
+            .tx_buffer
+            .get_mut(self.out_index..self.out_index + F::SAMPLE_BYTES)
+        else {
+            self.truncated = true;
+            return;
+        };
+        F::encode(x, y, beam_on, sample);
+        self.out_index += F::SAMPLE_BYTES;
+    }
+
+    /// Moves the samples from start_index on to make room for blanked samples in front
+    /// of them, which let the beam settle on the first sample.
+    /// Nothing is inserted if the buffer is full.
+    fn insert_settle_samples(&mut self, start_index: usize) {
+        if self.out_index + Self::SETTLE_BYTES > self.tx_buffer.len() {
+            self.truncated = true;
+            return;
+        }
+        self.tx_buffer.copy_within(
+            start_index..self.out_index,
+            start_index + Self::SETTLE_BYTES,
+        );
+        let (x, y) = self.raw_sample(start_index + Self::SETTLE_BYTES);
+        let end_index = self.out_index + Self::SETTLE_BYTES;
+        self.out_index = start_index;
+        for _ in 0..SETTLE_SAMPLES {
+            self.add_sample(x, y, false);
+        }
+        self.out_index = end_index;
+    }
+
+    /// Reads back the coordinates of the sample stored at the given byte index
+    fn raw_sample(&self, index: usize) -> (u16, u16) {
+        F::decode(&self.tx_buffer[index..index + F::SAMPLE_BYTES])
+    }
+
+    /// Position of the beam at the start and the end of a part
+    fn part_endpoints(&self, part: Part) -> ((u16, u16), (u16, u16)) {
+        (
+            self.raw_sample(part.0),
+            self.raw_sample(part.1 - F::SAMPLE_BYTES),
+        )
+    }
+
+    /// Registers all samples from start_index on as a new part.
+    /// If the part starts where the previous part has ended, both are chained
+    /// to a single part to avoid blanking the beam in between.
+    ///
+    /// If the blanking is part of the samples, every part starts and ends with blanked
+    /// samples instead. Parts are never chained then, as there is no interrupt to save.
+    pub fn finish_part(&mut self, start_index: usize, intensity: u8) {
+        if start_index == self.out_index {
+            return;
+        }
+
+        if F::BLANK_IN_SAMPLE {
+            self.insert_settle_samples(start_index);
+            let (x, y) = self.raw_sample(self.out_index - F::SAMPLE_BYTES);
+            for _ in 0..SETTLE_SAMPLES {
+                self.add_sample(x, y, false);
+            }
+        }
+
+        if self.is_connected_to_previous(self.parts.len(), start_index, intensity) {
+            self.parts.last_mut().unwrap().1 = self.out_index;
+        } else {
+            self.parts.push((start_index, self.out_index, intensity));
+        }
+    }
+
+    /// Checks if a part starting at start_index can be chained
+    /// to the part in front of the given position in the part list.
+    /// Only parts with the same intensity can be chained.
+    fn is_connected_to_previous(&self, position: usize, start_index: usize, intensity: u8) -> bool {
+        if F::BLANK_IN_SAMPLE {
+            return false;
+        }
+        match position.checked_sub(1).map(|p| self.parts[p]) {
+            Some(previous)
+                if previous.1 == start_index
+                    && previous.0 != previous.1
+                    && previous.2 == intensity =>
+            {
+                let end = self.part_endpoints(previous).1;
+                distance_squared(end, self.raw_sample(start_index)) <= Self::MERGE_DISTANCE_SQUARED
+            }
+            _ => false,
+        }
+    }
+
+    /// Chains all parts starting from first_part which are
+    /// stored consecutively and touch each other.
+    pub fn merge_connected_parts(&mut self, first_part: usize) {
+        let mut index = first_part.max(1);
+        while index < self.parts.len() {
+            let part = self.parts[index];
+            if self.is_connected_to_previous(index, part.0, part.2) {
+                self.parts[index - 1].1 = part.1;
+                self.parts.remove(index);
+            } else {
+                index += 1;
+            }
+        }
+    }
+
+    /// Total distance in DAC steps the beam travels blanked between the parts.
+    /// Starts and ends at the rest position.
+    pub fn blank_travel(&self) -> f32 {
+        let mut position = REST_POSITION;
+        let mut travel = 0.0;
+        for part in self.parts.iter().filter(|p| p.0 != p.1) {
+            let (start, end) = self.part_endpoints(*part);
+            travel += libm::sqrtf(distance_squared(position, start) as f32);
+            position = end;
+        }
+        travel + libm::sqrtf(distance_squared(position, REST_POSITION) as f32)
+    }
+
+    /// Reverse the order of samples of a part but keep every single sample intact
+    fn reverse_samples(&mut self, part: Part) {
+        let samples = &mut self.tx_buffer[part.0..part.1];
+        samples.reverse();
+        for sample in samples.chunks_exact_mut(F::SAMPLE_BYTES) {
+            sample.reverse();
+        }
+    }
+
+    /// Draws the parts starting from first_part in opposite order and direction.
+    /// Expects the parts to be stored consecutively. Reversing all of them at once
+    /// keeps them stored in drawing order.
+    pub fn reverse_order(&mut self, first_part: usize) {
+        let (Some(first), Some(last)) = (self.parts.get(first_part), self.parts.last()) else {
+            return;
+        };
+        let (start, end) = (first.0, last.1);
+        self.reverse_samples((start, end, FULL_INTENSITY));
+        for part in &mut self.parts[first_part..] {
+            *part = (start + end - part.1, start + end - part.0, part.2);
+        }
+        self.parts[first_part..].reverse();
+    }
+
+    /// Reorders and reverses the parts starting from `first_part` to reduce the
+    /// distance the beam has to jump between them.
+    ///
+    /// Uses a greedy nearest neighbour search. The samples are moved inside the buffer,
+    /// so the parts are still stored in drawing order afterwards.
+    /// Expects the parts to be stored consecutively, like the drawing functions do.
+    pub fn optimize_path(&mut self, first_part: usize) {
+        // Empty parts have no position and are just removed
+        let mut index = first_part;
+        while index < self.parts.len() {
+            if self.parts[index].0 == self.parts[index].1 {
+                self.parts.remove(index);
+            } else {
+                index += 1;
+            }
+        }
+
+        let mut position = match first_part.checked_sub(1) {
+            Some(previous) => self.part_endpoints(self.parts[previous]).1,
+            None => REST_POSITION,
+        };
+
+        for k in first_part..self.parts.len() {
+            // Find the part which can be reached with the shortest jump
+            let mut best = (k, false, u32::MAX);
+            for j in k..self.parts.len() {
+                let (start, end) = self.part_endpoints(self.parts[j]);
+                let forward = distance_squared(position, start);
+                let backward = distance_squared(position, end);
+                if forward < best.2 {
+                    best = (j, false, forward);
+                }
+                if backward < best.2 {
+                    best = (j, true, backward);
+                }
+            }
+            let (j, reverse, _) = best;
+            let (start, end, intensity) = self.parts[j];
+
+            if reverse {
+                self.reverse_samples(self.parts[j]);
+            }
+
+            // Move the selected part in front of all remaining ones
+            let length = end - start;
+            let region_start = self.parts[k].0;
+            self.tx_buffer[region_start..end].rotate_right(length);
+            for part in &mut self.parts[k..j] {
+                part.0 += length;
+                part.1 += length;
+            }
+            self.parts[k..=j].rotate_right(1);
+            self.parts[k] = (region_start, region_start + length, intensity);
+
+            position = self.part_endpoints(self.parts[k]).1;
+        }
+
+        // The new order might have placed parts next to each other
+        self.merge_connected_parts(first_part);
+    }
+
+    /// Completes the frame by parking the beam at the rest position.
+    /// Pads the frame with blanked samples until it is target_samples long,
+    /// which results in a constant frame period. A target of 0 disables the padding.
+    /// Returns false if the frame is already longer than the target.
+    ///
+    /// If the blanking is part of the samples, all parts are combined to a single one.
+    pub fn finish_frame(&mut self, target_samples: usize) -> bool {
+        let in_time = target_samples == 0 || self.samples() < target_samples;
+        let capacity = self.tx_buffer.len() / F::SAMPLE_BYTES;
+        let end = target_samples.max(self.samples() + 1).min(capacity);
+
+        if F::BLANK_IN_SAMPLE && !self.parts.is_empty() {
+            self.parts = alloc::vec![(0, self.out_index, FULL_INTENSITY)];
+        }
+
+        let start_index = self.out_index;
+        while self.samples() < end {
+            self.add_sample(REST_POSITION.0, REST_POSITION.1, false);
+        }
+        self.idle = (start_index, self.out_index);
+        in_time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec;
+
+    /// X and Y as little endian words, the beam in the top bit of X
+    struct Plain<const BLANK_IN_SAMPLE: bool>;
+
+    impl<const BLANK_IN_SAMPLE: bool> SampleFormat for Plain<BLANK_IN_SAMPLE> {
+        const RESOLUTION_BITS: u32 = 8;
+        const SAMPLE_BYTES: usize = 4;
+        const BLANK_IN_SAMPLE: bool = BLANK_IN_SAMPLE;
+
+        fn encode(x: u16, y: u16, beam_on: bool, sample: &mut [u8]) {
+            let x = x | (beam_on as u16) << 15;
+            sample[..2].copy_from_slice(&x.to_le_bytes());
+            sample[2..].copy_from_slice(&y.to_le_bytes());
+        }
+
+        fn decode(sample: &[u8]) -> (u16, u16) {
+            let x = u16::from_le_bytes([sample[0], sample[1]]);
+            (x & 0x7fff, u16::from_le_bytes([sample[2], sample[3]]))
+        }
+    }
+
+    type Frame<'a> = FrameBuffer<'a, Plain<false>>;
+
+    fn add_part<F: SampleFormat>(frame: &mut FrameBuffer<F>, points: &[(u16, u16)], intensity: u8) {
+        let start_index = frame.out_index;
+        for &(x, y) in points {
+            frame.add_native_point(x, y);
+        }
+        frame.finish_part(start_index, intensity);
+    }
+
+    /// Points of every part with its intensity
+    fn parts<F: SampleFormat>(frame: &FrameBuffer<F>) -> Vec<(Vec<(u16, u16)>, u8)> {
+        frame
+            .parts
+            .iter()
+            .map(|&(start, end, intensity)| {
+                let points = frame.tx_buffer[start..end]
+                    .chunks_exact(F::SAMPLE_BYTES)
+                    .map(F::decode)
+                    .collect();
+                (points, intensity)
+            })
+            .collect()
+    }
+
+    /// The parts cover the drawn samples without gaps
+    fn assert_consecutive<F: SampleFormat>(frame: &FrameBuffer<F>) {
+        let mut index = 0;
+        for part in &frame.parts {
+            assert_eq!(part.0, index, "{:?}", frame.parts);
+            index = part.1;
+        }
+        assert_eq!(index, frame.out_index);
+    }
+
+    #[test]
+    fn touching_parts_with_the_same_intensity_are_chained() {
+        let mut buffer = [0; 400];
+        let mut frame = Frame::new(&mut buffer);
+        add_part(&mut frame, &[(10, 10), (20, 10)], FULL_INTENSITY);
+        // Within 2 steps
+        add_part(&mut frame, &[(21, 11), (30, 10)], FULL_INTENSITY);
+        assert_eq!(frame.parts, [(0, 16, FULL_INTENSITY)]);
+        // 3 steps away
+        add_part(&mut frame, &[(33, 10)], FULL_INTENSITY);
+        // Another intensity
+        add_part(&mut frame, &[(33, 10), (40, 10)], 100);
+        // Empty parts are ignored
+        add_part(&mut frame, &[], 100);
+        assert_eq!(
+            frame.parts,
+            [
+                (0, 16, FULL_INTENSITY),
+                (16, 20, FULL_INTENSITY),
+                (20, 28, 100)
+            ]
+        );
+    }
+
+    #[test]
+    fn blanking_in_the_samples_surrounds_every_part_with_settle_samples() {
+        let mut buffer = [0; 400];
+        let mut frame = FrameBuffer::<Plain<true>>::new(&mut buffer);
+        add_part(&mut frame, &[(10, 10), (20, 10)], FULL_INTENSITY);
+        add_part(&mut frame, &[(20, 10), (30, 10)], FULL_INTENSITY);
+        let part_samples = 2 + 2 * SETTLE_SAMPLES;
+        assert_eq!(frame.parts.len(), 2);
+        assert_eq!(frame.samples(), 2 * part_samples);
+        assert_consecutive(&frame);
+
+        let beams: Vec<bool> = frame.tx_buffer[..frame.out_index]
+            .chunks_exact(4)
+            .map(|sample| sample[1] & 0x80 != 0)
+            .collect();
+        let mut expected = vec![false; SETTLE_SAMPLES];
+        expected.extend([true; 2]);
+        expected.extend([false; SETTLE_SAMPLES]);
+        assert_eq!(beams, [expected.clone(), expected].concat());
+
+        let mut expected = vec![(10, 10); SETTLE_SAMPLES + 1];
+        expected.extend([(20, 10); SETTLE_SAMPLES + 1]);
+        assert_eq!(parts(&frame)[0].0, expected);
+    }
+
+    #[test]
+    fn merge_connected_parts() {
+        let mut buffer = [0; 400];
+        let mut frame = Frame::new(&mut buffer);
+        // Different intensities keep them apart while drawing
+        add_part(&mut frame, &[(0, 0), (10, 0)], 1);
+        add_part(&mut frame, &[(11, 0), (20, 0)], 2);
+        add_part(&mut frame, &[(21, 1), (30, 0)], 3);
+        add_part(&mut frame, &[(100, 100)], 4);
+        add_part(&mut frame, &[(100, 101)], 5);
+        for part in &mut frame.parts {
+            part.2 = FULL_INTENSITY;
+        }
+
+        // Only the parts from the first one on are chained to their predecessors
+        frame.merge_connected_parts(3);
+        assert_eq!(frame.parts.len(), 4);
+        assert_eq!(frame.parts[3], (24, 32, FULL_INTENSITY));
+
+        frame.merge_connected_parts(0);
+        assert_eq!(
+            frame.parts,
+            [(0, 24, FULL_INTENSITY), (24, 32, FULL_INTENSITY)]
+        );
+    }
+
+    #[test]
+    fn reverse_order() {
+        let mut buffer = [0; 400];
+        let mut frame = Frame::new(&mut buffer);
+        add_part(&mut frame, &[(1, 1), (2, 1)], 10);
+        add_part(&mut frame, &[(50, 50), (51, 50), (52, 50)], 20);
+        add_part(&mut frame, &[(90, 0)], 30);
+
+        frame.reverse_order(1);
+        assert_eq!(
+            parts(&frame),
+            [
+                (vec![(1, 1), (2, 1)], 10),
+                (vec![(90, 0)], 30),
+                (vec![(52, 50), (51, 50), (50, 50)], 20),
+            ]
+        );
+        assert_consecutive(&frame);
+
+        frame.reverse_order(0);
+        assert_eq!(
+            parts(&frame),
+            [
+                (vec![(50, 50), (51, 50), (52, 50)], 20),
+                (vec![(90, 0)], 30),
+                (vec![(2, 1), (1, 1)], 10),
+            ]
+        );
+        assert_consecutive(&frame);
+
+        // Nothing to reverse
+        frame.reverse_order(3);
+        assert_eq!(frame.parts.len(), 3);
+    }
+
+    #[test]
+    fn optimize_path_jumps_to_the_nearest_end() {
+        let mut buffer = [0; 400];
+        let mut frame = Frame::new(&mut buffer);
+        add_part(&mut frame, &[(200, 200), (250, 200)], 1);
+        add_part(&mut frame, &[(60, 10), (100, 100)], 2);
+        add_part(&mut frame, &[(40, 0), (5, 5)], 3);
+        let travel = frame.blank_travel();
+
+        frame.optimize_path(0);
+        assert_eq!(
+            parts(&frame),
+            [
+                // Closest to the rest position, drawn backwards
+                (vec![(5, 5), (40, 0)], 3),
+                (vec![(60, 10), (100, 100)], 2),
+                (vec![(200, 200), (250, 200)], 1),
+            ]
+        );
+        assert_consecutive(&frame);
+        assert!(frame.blank_travel() < travel);
+    }
+
+    #[test]
+    fn optimize_path_removes_empty_parts_and_chains_neighbours() {
+        let mut buffer = [0; 400];
+        let mut frame = Frame::new(&mut buffer);
+        add_part(&mut frame, &[(0, 0), (10, 0)], FULL_INTENSITY);
+        add_part(&mut frame, &[(100, 100), (120, 100)], FULL_INTENSITY);
+        frame.parts.push((16, 16, FULL_INTENSITY));
+        add_part(&mut frame, &[(20, 0), (11, 0)], FULL_INTENSITY);
+
+        frame.optimize_path(0);
+        assert_eq!(
+            parts(&frame),
+            [
+                (vec![(0, 0), (10, 0), (11, 0), (20, 0)], FULL_INTENSITY),
+                (vec![(100, 100), (120, 100)], FULL_INTENSITY),
+            ]
+        );
+        assert_consecutive(&frame);
+    }
+
+    #[test]
+    fn optimize_path_continues_after_the_first_part() {
+        let mut buffer = [0; 400];
+        let mut frame = Frame::new(&mut buffer);
+        add_part(&mut frame, &[(0, 0), (200, 200)], 1);
+        add_part(&mut frame, &[(10, 10), (20, 20)], 2);
+        add_part(&mut frame, &[(190, 190), (180, 180)], 3);
+
+        frame.optimize_path(1);
+        assert_eq!(
+            parts(&frame),
+            [
+                (vec![(0, 0), (200, 200)], 1),
+                (vec![(190, 190), (180, 180)], 3),
+                (vec![(20, 20), (10, 10)], 2),
+            ]
+        );
+        assert_consecutive(&frame);
+    }
+
+    #[test]
+    fn drawing_stops_before_the_reserved_samples() {
+        let capacity = 20;
+        let mut buffer = [0; 20 * 4];
+        let mut frame = Frame::new(&mut buffer);
+        let points = [(1, 1); 20];
+        add_part(
+            &mut frame,
+            &points[..capacity - RESERVED_SAMPLES],
+            FULL_INTENSITY,
+        );
+        assert!(!frame.truncated);
+        add_part(&mut frame, &points[..1], FULL_INTENSITY);
+        assert!(frame.truncated);
+        assert_eq!(frame.samples(), capacity - RESERVED_SAMPLES);
+
+        // The beam is still parked
+        assert!(frame.finish_frame(0));
+        assert_eq!(frame.samples(), capacity - RESERVED_SAMPLES + 1);
+        assert_eq!(frame.idle, (frame.out_index - 4, frame.out_index));
+    }
+
+    #[test]
+    fn reserved_samples_cover_the_blanking_of_a_full_buffer() {
+        let capacity = 30;
+        let mut buffer = [0; 30 * 4];
+        let mut frame = FrameBuffer::<Plain<true>>::new(&mut buffer);
+        let points = [(1, 1); 30];
+        add_part(
+            &mut frame,
+            &points[..capacity - RESERVED_SAMPLES],
+            FULL_INTENSITY,
+        );
+        assert!(!frame.truncated);
+        assert_eq!(frame.samples(), capacity - 1);
+
+        assert!(frame.finish_frame(0));
+        assert!(!frame.truncated);
+        assert_eq!(frame.samples(), capacity);
+        // A single transfer with the idle sample at the end
+        assert_eq!(frame.parts, [(0, (capacity - 1) * 4, FULL_INTENSITY)]);
+        assert_eq!(frame.idle, ((capacity - 1) * 4, capacity * 4));
+
+        frame.add_native_point(1, 1);
+        assert!(frame.truncated);
+        assert_eq!(frame.samples(), capacity);
+    }
+
+    #[test]
+    fn finish_frame_pads_to_the_target() {
+        let mut buffer = [0; 20 * 4];
+        let mut frame = Frame::new(&mut buffer);
+        add_part(&mut frame, &[(1, 1), (2, 2), (3, 3)], FULL_INTENSITY);
+        assert!(frame.finish_frame(10));
+        assert_eq!(frame.samples(), 10);
+        assert_eq!(frame.idle, (12, 40));
+        assert_eq!(frame.parts, [(0, 12, FULL_INTENSITY)]);
+
+        // Too late, still parked for one sample
+        let mut buffer = [0; 20 * 4];
+        let mut frame = Frame::new(&mut buffer);
+        add_part(&mut frame, &[(1, 1), (2, 2), (3, 3)], FULL_INTENSITY);
+        assert!(!frame.finish_frame(2));
+        assert_eq!(frame.idle, (12, 16));
+
+        // Limited by the buffer
+        let mut buffer = [0; 20 * 4];
+        let mut frame = Frame::new(&mut buffer);
+        assert!(frame.finish_frame(1000));
+        assert_eq!(frame.idle, (0, 80));
+        assert!(!frame.truncated);
+    }
+}
@@ -0,0 +1,203 @@
+use heapless::spsc::{Consumer, Producer, Queue};
+
+/// Hands frames between the drawing task and the display interrupt without a lock.
+///
+/// Two frames circulate. One of them is shown by the DMA while the other one
+/// is either drawn by the task or waits to be shown next.
+/// Every frame is owned by exactly one place at any time. The frames are moved
+/// between the places, so the one read by the DMA can never be handed out for drawing.
+///
+/// Drawn frames and free frames each travel through a single producer single consumer
+/// queue. Neither side has to wait for the other, even if they run on different cores.
+///
+/// Independent of the hardware to allow reasoning about it without a scope.
+pub struct FrameExchange<F> {
+    /// Completely drawn and waiting to be shown. Holds one frame.
+    drawn: Queue<F, 2>,
+    /// Free to be drawn on. Holds one frame.
+    free: Queue<F, 2>,
+}
+
+impl<F> FrameExchange<F> {
+    pub const fn new() -> Self {
+        Self {
+            drawn: Queue::new(),
+            free: Queue::new(),
+        }
+    }
+
+    /// Starts by showing `current` and `next` afterwards.
+    /// Returns the ends used by the drawing task and by the display.
+    pub fn split(&mut self, current: F, next: F) -> (DrawingEnd<'_, F>, DisplayEnd<'_, F>) {
+        let (mut drawn_producer, drawn_consumer) = self.drawn.split();
+        let (free_producer, free_consumer) = self.free.split();
+        if drawn_producer.enqueue(next).is_err() {
+            unreachable!("The queue starts empty");
+        }
+        (
+            DrawingEnd {
+                drawn: drawn_producer,
+                free: free_consumer,
+            },
+            DisplayEnd {
+                current,
+                drawn: drawn_consumer,
+                free: free_producer,
+            },
+        )
+    }
+}
+
+impl<F> Default for FrameExchange<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct DrawingEnd<'a, F> {
+    drawn: Producer<'a, F, 2>,
+    free: Consumer<'a, F, 2>,
+}
+
+impl<'a, F> DrawingEnd<'a, F> {
+    /// Hands out the free frame for drawing, if there is one.
+    pub fn take_canvas(&mut self) -> Option<F> {
+        self.free.dequeue()
+    }
+
+    /// Returns a drawn frame to be shown next.
+    ///
+    /// Only one frame can be outside at a time, so there is never
+    /// a waiting frame at this point.
+    pub fn submit(&mut self, frame: F) {
+        if self.drawn.enqueue(frame).is_err() {
+            panic!("Only one frame can wait to be shown");
+        }
+    }
+}
+
+pub struct DisplayEnd<'a, F> {
+    /// Shown by the DMA
+    current: F,
+    drawn: Consumer<'a, F, 2>,
+    free: Producer<'a, F, 2>,
+}
+
+impl<'a, F> DisplayEnd<'a, F> {
+    /// Frame which is shown right now
+    pub fn current(&self) -> &F {
+        &self.current
+    }
+
+    pub fn current_mut(&mut self) -> &mut F {
+        &mut self.current
+    }
+
+    /// Frame which waits to be shown, if there is one
+    pub fn next(&self) -> Option<&F> {
+        self.drawn.peek()
+    }
+
+    /// To be called after the current frame was shown completely.
+    /// Switches to the next frame and frees the shown one for drawing.
+    /// Returns false if there was no new frame and the current one must be repeated.
+    pub fn advance(&mut self) -> bool {
+        match self.drawn.dequeue() {
+            Some(next) => {
+                let shown = core::mem::replace(&mut self.current, next);
+                // The drawing task gave its frame back, so nothing is free
+                if self.free.enqueue(shown).is_err() {
+                    unreachable!("Only one frame can be free");
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn starts_with_both_frames_taken() {
+        let mut exchange = FrameExchange::new();
+        let (mut drawing, display) = exchange.split('a', 'b');
+        assert_eq!(*display.current(), 'a');
+        assert_eq!(display.next(), Some(&'b'));
+        assert_eq!(drawing.take_canvas(), None);
+    }
+
+    #[test]
+    fn advance_frees_the_shown_frame() {
+        let mut exchange = FrameExchange::new();
+        let (mut drawing, mut display) = exchange.split('a', 'b');
+        assert!(display.advance());
+        assert_eq!(*display.current(), 'b');
+        assert_eq!(display.next(), None);
+        assert_eq!(drawing.take_canvas(), Some('a'));
+        assert_eq!(drawing.take_canvas(), None);
+    }
+
+    #[test]
+    fn current_frame_is_repeated_without_a_new_one() {
+        let mut exchange = FrameExchange::new();
+        let (mut drawing, mut display) = exchange.split('a', 'b');
+        assert!(display.advance());
+        assert!(!display.advance());
+        assert_eq!(*display.current(), 'b');
+        assert_eq!(drawing.take_canvas(), Some('a'));
+        // Still drawn on
+        assert!(!display.advance());
+        assert_eq!(*display.current(), 'b');
+    }
+
+    #[test]
+    fn submitted_frame_is_shown_next() {
+        let mut exchange = FrameExchange::new();
+        let (mut drawing, mut display) = exchange.split(0, 1);
+        display.advance();
+        let mut canvas = drawing.take_canvas().unwrap();
+        canvas += 2;
+        drawing.submit(canvas);
+        assert_eq!(display.next(), Some(&2));
+        assert!(display.advance());
+        assert_eq!(*display.current(), 2);
+        assert_eq!(drawing.take_canvas(), Some(1));
+    }
+
+    #[test]
+    fn shown_frame_is_never_handed_out() {
+        let mut exchange = FrameExchange::new();
+        let (mut drawing, mut display) = exchange.split(0u32, 1u32);
+        let mut canvas = None;
+        let mut next_id = 2;
+        // Both sides running at different speeds
+        for step in 0..1000 {
+            if step % 3 == 0 {
+                display.advance();
+            }
+            if step % 2 == 0 {
+                canvas = canvas.or_else(|| drawing.take_canvas());
+            } else if canvas.take().is_some() {
+                // Drawn with new content
+                drawing.submit(next_id);
+                next_id += 1;
+            }
+            if let Some(frame) = canvas {
+                assert_ne!(frame, *display.current());
+                assert_ne!(Some(&frame), display.next());
+            }
+        }
+        assert!(next_id > 100);
+    }
+
+    #[test]
+    #[should_panic]
+    fn only_one_frame_can_wait() {
+        let mut exchange = FrameExchange::new();
+        let (mut drawing, _display) = exchange.split('a', 'b');
+        drawing.submit('c');
+    }
+}
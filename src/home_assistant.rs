@@ -0,0 +1,206 @@
+//! Announces the clock to Home Assistant using MQTT discovery.
+//!
+//! Every entity gets a retained config below the discovery prefix. The entities
+//! send their commands to the command topics of the clock and read their values
+//! from a common state message, which is published together with the telemetry.
+
+use alloc::string::String;
+use core::fmt::Write;
+use core::ops::RangeInclusive;
+use core::sync::atomic::Ordering;
+
+use crate::mqtt_client::{self, QueueFull};
+use crate::night_mode::{self, DisplayMode};
+use crate::{limits, message, mqtt, ntptime, picture, scopeclock, telemetry, test_pattern};
+
+const DISCOVERY_PREFIX: &str = "homeassistant";
+
+struct Entity {
+    component: &'static str,
+    object: &'static str,
+    name: &'static str,
+    /// Topic receiving the commands. None for sensors.
+    command_topic: Option<&'static str>,
+    /// Lowest and highest value as accepted by the command. Length of the text for texts.
+    bounds: Option<RangeInclusive<u32>>,
+    /// Further members of the config, each starting with a comma
+    extra: &'static str,
+}
+
+const ENTITIES: [Entity; 9] = [
+    Entity {
+        component: "switch",
+        object: "display",
+        name: "Display",
+        command_topic: Some("display_mode"),
+        bounds: None,
+        extra: r#","payload_on":"auto","payload_off":"off","state_on":"auto","state_off":"off","icon":"mdi:clock-outline""#,
+    },
+    Entity {
+        component: "number",
+        object: "brightness",
+        name: "Brightness",
+        command_topic: Some("brightness"),
+        bounds: Some(limits::BRIGHTNESS),
+        extra: r#","step":10,"unit_of_measurement":"%""#,
+    },
+    Entity {
+        component: "select",
+        object: "face",
+        name: "Face",
+        command_topic: Some("display_mode"),
+        bounds: None,
+        extra: r#","options":["auto","normal","dim","minimal","off"],"icon":"mdi:clock-time-four-outline""#,
+    },
+    Entity {
+        component: "select",
+        object: "test_pattern",
+        name: "Test pattern",
+        command_topic: Some("test_pattern"),
+        bounds: None,
+        extra: r#","options":["none","crosshatch","circles","ramp","dots","corners","blank_timing"],"entity_category":"config""#,
+    },
+    Entity {
+        component: "number",
+        object: "beam_off",
+        name: "Wait before beam off",
+        command_topic: Some("beam_off"),
+        bounds: Some(limits::WAIT_BEFORE_BEAM_OFF),
+        extra: r#","mode":"box","entity_category":"config""#,
+    },
+    Entity {
+        component: "number",
+        object: "beam_on",
+        name: "Wait after beam on",
+        command_topic: Some("beam_on"),
+        bounds: Some(limits::WAIT_AFTER_BEAM_ON),
+        extra: r#","mode":"box","unit_of_measurement":"ns","entity_category":"config""#,
+    },
+    Entity {
+        component: "sensor",
+        object: "ntp_offset",
+        name: "NTP offset",
+        command_topic: None,
+        bounds: None,
+        extra: r#","unit_of_measurement":"µs","state_class":"measurement","entity_category":"diagnostic""#,
+    },
+    Entity {
+        component: "sensor",
+        object: "rssi",
+        name: "WiFi signal",
+        command_topic: None,
+        bounds: None,
+        extra: r#","device_class":"signal_strength","unit_of_measurement":"dBm","state_class":"measurement","entity_category":"diagnostic""#,
+    },
+    Entity {
+        component: "text",
+        object: "message",
+        name: "Message",
+        command_topic: Some("message"),
+        bounds: Some(0..=limits::MESSAGE_LENGTH as u32),
+        extra: r#","icon":"mdi:message-text""#,
+    },
+];
+
+fn config(entity: &Entity, device_id: &str) -> String {
+    let mut config = String::new();
+    write!(
+        config,
+        r#"{{"name":"{}","unique_id":"{}_{}","state_topic":"{}","value_template":"{{{{ value_json.{} }}}}""#,
+        entity.name,
+        device_id,
+        entity.object,
+        mqtt::topic(device_id, "state"),
+        entity.object
+    )
+    .ok();
+    if let Some(topic) = entity.command_topic {
+        let topic = mqtt::command_topic(device_id, topic);
+        write!(config, r#","command_topic":"{}""#, topic).ok();
+    }
+    if let Some(bounds) = &entity.bounds {
+        write!(
+            config,
+            r#","min":{},"max":{}"#,
+            bounds.start(),
+            bounds.end()
+        )
+        .ok();
+    }
+    write!(
+        config,
+        r#"{},"availability_topic":"{}","device":{{"identifiers":["{}"],"name":"Scope Clock","model":"ESP32 scope clock","sw_version":"{}"}}}}"#,
+        entity.extra,
+        mqtt::topic(device_id, "availability"),
+        device_id,
+        env!("CARGO_PKG_VERSION")
+    )
+    .ok();
+    config
+}
+
+/// Queues the retained configs of all entities.
+/// To be repeated after every connect, as Home Assistant might have lost them.
+pub fn publish_discovery(device_id: &str) -> Result<(), QueueFull> {
+    for entity in &ENTITIES {
+        let topic = alloc::format!(
+            "{}/{}/{}/{}/config",
+            DISCOVERY_PREFIX,
+            entity.component,
+            device_id,
+            entity.object
+        );
+        mqtt_client::publish(&topic, config(entity, device_id).as_bytes(), true, true)?;
+    }
+    Ok(())
+}
+
+/// Appends the text as content of a JSON string
+fn push_escaped(json: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if c.is_control() => {
+                write!(json, "\\u{:04x}", c as u32).ok();
+            }
+            c => json.push(c),
+        }
+    }
+}
+
+/// Current values of all entities as JSON object
+pub fn state_json() -> String {
+    let mode = night_mode::override_mode();
+    let display = match mode {
+        Some(DisplayMode::Off) => "off",
+        _ => "auto",
+    };
+    // The face forced from the network, or auto for the night rules
+    let face = mode.map_or("auto", |mode| mode.name());
+    let pattern = test_pattern::selected().map_or("none", |pattern| pattern.name());
+
+    let mut json = String::new();
+    write!(
+        json,
+        r#"{{"display":"{}","brightness":{},"face":"{}","test_pattern":"{}","beam_off":{},"beam_on":{},"ntp_offset":{}"#,
+        display,
+        picture::BRIGHTNESS.load(Ordering::Relaxed),
+        face,
+        pattern,
+        scopeclock::WAIT_BEFORE_BEAM_OFF.load(Ordering::Relaxed),
+        scopeclock::WAIT_AFTER_BEAM_ON.load(Ordering::Relaxed),
+        ntptime::LAST_OFFSET.load(Ordering::Relaxed)
+    )
+    .ok();
+    if let Some(rssi) = telemetry::wifi_rssi() {
+        write!(json, r#","rssi":{}"#, rssi).ok();
+    }
+    json.push_str(r#","message":""#);
+    if let Some((text, _)) = message::current() {
+        push_escaped(&mut json, &text);
+    }
+    json.push('"');
+    json.push('}');
+    json
+}
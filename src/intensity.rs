@@ -0,0 +1,121 @@
+//! Analog Z output to control the brightness of the beam.
+//!
+//! The on/off blanking on GPIO 32 stays as it is. The intensity is an
+//! additional signal for scopes with a DC coupled Z input.
+//! It is set by the display driver whenever a part starts.
+
+#[path = "util.rs"]
+mod examples_util;
+
+/// True if there is a backend which can actually change the intensity
+pub const AVAILABLE: bool = cfg!(feature = "analog-z");
+
+/// Backend for the analog Z output
+pub trait Intensity {
+    /// Called from the display interrupt before the beam is turned on.
+    /// 0 is the darkest and 255 the brightest level.
+    fn set_level(&mut self, level: u8);
+}
+
+/// Used if there is no analog Z output. All parts are drawn with the same intensity.
+pub struct NoIntensity;
+
+impl Intensity for NoIntensity {
+    fn set_level(&mut self, _level: u8) {}
+}
+
+#[cfg(not(feature = "analog-z"))]
+pub type IntensityOutput = NoIntensity;
+
+#[cfg(feature = "analog-z")]
+pub type IntensityOutput = ledc::LedcIntensity;
+
+#[cfg(feature = "analog-z")]
+pub mod ledc {
+    use super::examples_util::hal;
+    use hal::clock::Clocks;
+    use hal::gpio::{GpioPin, Output, PushPull};
+    use hal::ledc::channel::{self, ChannelIFace};
+    use hal::ledc::timer::{self, TimerIFace};
+    use hal::ledc::{HighSpeed, LEDC};
+    use hal::peripherals;
+    use hal::prelude::*;
+
+    use super::Intensity;
+
+    /// PWM frequency. An RC low pass behind the pin turns the PWM into a voltage.
+    /// Faster is better for the settling time of the filter.
+    /// 8 bit at 80 MHz allow up to 312.5 kHz.
+    const PWM_FREQUENCY_KHZ: u32 = 250;
+
+    /// Generates the intensity as PWM with the LED controller on GPIO 33.
+    ///
+    /// The filtered voltage needs a few periods of the PWM to settle.
+    /// Parts which are shorter than that will be drawn with a mixture
+    /// of their own intensity and the one of the part before.
+    pub struct LedcIntensity {
+        ledc: peripherals::LEDC,
+        _pin: GpioPin<Output<PushPull>, 33>,
+    }
+
+    // SAFETY: Only used from inside the critical section guarding the display driver
+    unsafe impl Send for LedcIntensity {}
+
+    impl LedcIntensity {
+        pub fn new(
+            mut ledc: peripherals::LEDC,
+            mut pin: GpioPin<Output<PushPull>, 33>,
+            clocks: &Clocks,
+        ) -> Self {
+            // The drivers are only used for the configuration. Afterwards the
+            // duty is changed by writing the registers directly to keep the
+            // interrupt short and to avoid borrowing the clocks forever.
+            let controller = LEDC::new(&mut ledc, clocks);
+            let mut hstimer0 = controller.get_timer::<HighSpeed>(timer::Number::Timer0);
+            hstimer0
+                .configure(timer::config::Config {
+                    duty: timer::config::Duty::Duty8Bit,
+                    clock_source: timer::HSClockSource::APBClk,
+                    frequency: PWM_FREQUENCY_KHZ.kHz(),
+                })
+                .unwrap();
+            let mut channel0 = controller.get_channel(channel::Number::Channel0, &mut pin);
+            channel0
+                .configure(channel::config::Config {
+                    timer: &hstimer0,
+                    duty_pct: 100,
+                    pin_config: channel::config::PinConfig::PushPull,
+                })
+                .unwrap();
+
+            Self { ledc, _pin: pin }
+        }
+    }
+
+    impl Intensity for LedcIntensity {
+        #[ram]
+        fn set_level(&mut self, level: u8) {
+            // A duty of 256 is required to stay high all the time
+            let duty = match level {
+                u8::MAX => 256,
+                level => level as u32,
+            };
+            // The lowest 4 bits of the duty are fractional
+            self.ledc
+                .hsch0_duty()
+                .write(|w| unsafe { w.duty().bits(duty << 4) });
+            self.ledc.hsch0_conf1().write(|w| unsafe {
+                w.duty_start()
+                    .set_bit()
+                    .duty_inc()
+                    .set_bit()
+                    .duty_num()
+                    .bits(1)
+                    .duty_cycle()
+                    .bits(1)
+                    .duty_scale()
+                    .bits(0)
+            });
+        }
+    }
+}
@@ -0,0 +1,119 @@
+#[path = "util.rs"]
+mod examples_util;
+
+use esp_hal::i2s::{DataFormat, I2s, I2sTx, I2sWriteDma, Standard};
+use examples_util::hal;
+use hal::analog::dac::{set_dma_mode, DAC1, DAC2};
+use hal::clock::Clocks;
+use hal::delay::Delay;
+use hal::dma::{DmaPriority, I2s0DmaChannel, I2s0DmaChannelCreator};
+use hal::gpio::{Analog, GpioPin};
+use hal::peripherals::{self, Interrupt, I2S0};
+use hal::prelude::*;
+use hal::Blocking;
+
+use crate::display_backend::{DisplayBackend, I2sClock, I2sInterrupts, UnsupportedRate};
+
+/// Peripherals used by the internal DACs
+pub struct InternalDacResources {
+    pub i2s: I2S0,
+    pub dma_channel: I2s0DmaChannelCreator,
+    pub dac1: peripherals::DAC1,
+    pub dac2: peripherals::DAC2,
+    /// X output
+    pub dac1_pin: GpioPin<Analog, 25>,
+    /// Y output
+    pub dac2_pin: GpioPin<Analog, 26>,
+}
+
+/// The 2 internal 8 bit DACs of the ESP32, fed by I2S0 in DAC mode
+pub struct InternalDac {
+    tx: I2sTx<'static, I2S0, I2s0DmaChannel, Blocking>,
+    i2s_interrupts: I2sInterrupts,
+    clock: I2sClock,
+}
+
+impl DisplayBackend for InternalDac {
+    type Resources = InternalDacResources;
+
+    const RESOLUTION_BITS: u32 = 8;
+    const SAMPLE_BYTES: usize = 4;
+    const SAMPLE_RATE: u32 = 44100 * 3;
+    const INTERRUPT: Interrupt = Interrupt::I2S0;
+    const I2S_BASE: u32 = 0x3ff4f000;
+    const BLANK_IN_SAMPLE: bool = false;
+
+    fn new(
+        resources: InternalDacResources,
+        tx_descriptors: &'static mut [u32],
+        rx_descriptors: &'static mut [u32],
+        clocks: &Clocks,
+    ) -> Self {
+        // The DAC drivers only configure the pads. They aren't required afterwards.
+        let _dac1 = DAC1::new(resources.dac1, resources.dac1_pin);
+        let _dac2 = DAC2::new(resources.dac2, resources.dac2_pin);
+        set_dma_mode(true);
+
+        let i2s_interrupts = I2sInterrupts::new(&resources.i2s);
+        let i2s = I2s::new(
+            resources.i2s,
+            Standard::DAC,
+            DataFormat::Data16Channel16,
+            Self::SAMPLE_RATE.Hz(),
+            resources.dma_channel.configure(
+                false,
+                tx_descriptors,
+                rx_descriptors,
+                DmaPriority::Priority0,
+            ),
+            clocks,
+        );
+
+        Self {
+            tx: i2s.i2s_tx.build(),
+            i2s_interrupts,
+            clock: I2sClock::capture(Self::I2S_BASE, Self::SAMPLE_RATE),
+        }
+    }
+
+    /// Each DAC takes the upper byte of its 16 bit channel
+    fn encode(x: u16, y: u16, _beam_on: bool, sample: &mut [u8]) {
+        sample[0] = 0;
+        sample[1] = x as u8;
+        sample[2] = 0;
+        sample[3] = y as u8;
+    }
+
+    fn decode(sample: &[u8]) -> (u16, u16) {
+        (sample[1] as u16, sample[3] as u16)
+    }
+
+    #[ram]
+    fn write_blocking(&mut self, samples: &[u8], delay: &Delay) {
+        let transfer = self.tx.write_dma(&samples).unwrap();
+        // delay to give the DMA some time to activate
+        delay.delay_nanos(10);
+        transfer.wait().unwrap();
+    }
+
+    #[ram]
+    unsafe fn start_write(&mut self, samples: &[u8]) {
+        let transfer = self.tx.write_dma(&samples).unwrap();
+        // Dropping the transfer would wait for its end. It can't be kept either, as it
+        // borrows the samples and the I2S. The caller guarantees that the samples outlive
+        // the DMA and that the I2S isn't used before the end.
+        core::mem::forget(transfer);
+    }
+
+    fn set_sample_rate(&mut self, rate: u32) -> Result<(), UnsupportedRate> {
+        self.clock.set_rate(rate)
+    }
+
+    fn clear_tx_rempty(&self) {
+        self.i2s_interrupts.clear_tx_rempty();
+    }
+
+    fn enable_tx_rempty(&self) {
+        self.i2s_interrupts.enable_tx_rempty();
+    }
+}
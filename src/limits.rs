@@ -0,0 +1,112 @@
+//! Bounds of the settings which can be changed over the network.
+//!
+//! Plain constants without any dependency on the hardware, so the commands
+//! can be parsed and checked on the host. The modules applying the settings
+//! refer to the same constants.
+
+use core::ops::RangeInclusive;
+
+/// Highest logical coordinate, 255 DAC steps at the scale of the clock face
+pub const MAX_COORDINATE: f32 = 510.0;
+
+/// Size of each of the two frame buffers in bytes
+pub const FRAME_BUFFER_BYTES: usize = 50000;
+
+/// Bytes every drawn point takes in the frame buffer. Two dithered samples
+/// of 4 bytes with the internal DAC, a single sample of 8 bytes with the MCP4922.
+pub const POINT_BYTES: usize = 8;
+
+/// Points fitting into a frame buffer
+pub const FRAME_POINTS: u32 = (FRAME_BUFFER_BYTES / POINT_BYTES) as u32;
+
+/// Points of the clock face with the default settings, measured from the
+/// length of its strokes and the exposure of its dots
+pub const FACE_POINTS: u32 = 5400;
+
+/// Sharpness of all vertices of the clock face added up, a full reversal counting as 1.
+/// Each sample of corner dwell costs at most this many points.
+const FACE_CORNERS: u32 = 90;
+
+/// Busy loops before the blanking at the end of a part. Stalls the NMI.
+pub const WAIT_BEFORE_BEAM_OFF: RangeInclusive<u32> = 0..=5000;
+
+/// Nanoseconds the beam rests on the first sample of a part. Stalls the NMI.
+pub const WAIT_AFTER_BEAM_ON: RangeInclusive<u32> = 0..=100_000;
+
+/// Thousandths of a logical unit per sample. Slower beams need more points, the lower
+/// bound keeps the face inside the frame buffer. Beyond 4 DAC steps per sample
+/// the lines fall apart into dots.
+pub const BEAM_SPEED: RangeInclusive<u32> = (FACE_POINTS * 1000).div_ceil(FRAME_POINTS)..=8000;
+
+/// Clock of the I2S units in Hz, divided by the MCLK divider N + b/a
+pub const I2S_SOURCE_CLOCK: u32 = 160_000_000;
+
+/// The I2S driver runs MCLK at 256 times the rate of I2S frames with 16 bit data
+const MCLK_PER_I2S_FRAME: u32 = 256;
+
+/// Integer part N of the MCLK divider. Needs to be at least 2 and has 8 bits.
+pub const CLOCK_DIVIDER: RangeInclusive<u32> = 2..=255;
+
+/// The internal DAC takes one I2S frame per sample
+#[cfg(not(feature = "mcp4922"))]
+pub const I2S_FRAMES_PER_SAMPLE: u32 = 1;
+/// The MCP4922 takes one I2S frame per channel
+#[cfg(feature = "mcp4922")]
+pub const I2S_FRAMES_PER_SAMPLE: u32 = 2;
+
+/// Highest sample rate the internal DAC can follow
+#[cfg(not(feature = "mcp4922"))]
+const MAX_DAC_SAMPLE_RATE: u32 = 250_000;
+/// The MCP4922 needs about 4.5µs to settle after a full scale step
+#[cfg(feature = "mcp4922")]
+const MAX_DAC_SAMPLE_RATE: u32 = 150_000;
+
+/// In Hz. The slowest rate needs a divider just below 256, the fastest one a divider of 2,
+/// unless the DAC can't follow.
+pub const SAMPLE_RATE: RangeInclusive<u32> = {
+    let mclk_per_sample = MCLK_PER_I2S_FRAME * I2S_FRAMES_PER_SAMPLE;
+    let min = I2S_SOURCE_CLOCK / (mclk_per_sample * (*CLOCK_DIVIDER.end() + 1)) + 1;
+    let max = I2S_SOURCE_CLOCK / (mclk_per_sample * *CLOCK_DIVIDER.start());
+    min..=if max < MAX_DAC_SAMPLE_RATE {
+        max
+    } else {
+        MAX_DAC_SAMPLE_RATE
+    }
+};
+
+/// In percent
+pub const BRIGHTNESS: RangeInclusive<u32> = 10..=400;
+
+/// Samples per vertex. The face must still fit into the frame buffer with the
+/// dwell added to all of its corners.
+pub const CORNER_DWELL: RangeInclusive<u32> = 0..=(FRAME_POINTS - FACE_POINTS) / FACE_CORNERS;
+
+/// Logical units beyond a vertex. Further out the overshoot becomes visible as a spike.
+pub const CORNER_OVERSHOOT: RangeInclusive<u32> = 0..=10;
+
+/// In Hz. 0 disables the padding and is accepted in addition.
+/// Slower frames flicker and let the beam rest on the idle position for too long.
+/// Faster ones can't be reached with the face anyway.
+pub const FRAME_RATE: RangeInclusive<u32> = 10..=100;
+
+/// In DAC steps. The bezel keeps 5 steps distance to the edge of the DAC range.
+pub const ORBIT_RADIUS: RangeInclusive<u32> = 0..=0x82 - 0x7d;
+
+/// Longest message in bytes
+pub const MESSAGE_LENGTH: usize = 120;
+
+/// Seconds a message is shown
+pub const MESSAGE_TIMEOUT: RangeInclusive<u32> = 1..=24 * 3600;
+
+/// Calibration shift in logical units. At most half the screen in each direction.
+pub const CALIBRATION_OFFSET: RangeInclusive<f32> = -MAX_COORDINATE / 2.0..=MAX_COORDINATE / 2.0;
+
+/// Calibration factor of the size. A gain near 0 would draw the whole frame
+/// onto a single spot and burn the tube.
+pub const CALIBRATION_GAIN: RangeInclusive<f32> = 0.5..=2.0;
+
+/// Calibration rotation in degrees
+pub const CALIBRATION_ROTATION: RangeInclusive<f32> = -180.0..=180.0;
+
+/// Calibration change of the width or height per logical unit, relative to the center
+pub const CALIBRATION_KEYSTONE: RangeInclusive<f32> = -0.5..=0.5;
@@ -22,9 +22,10 @@ use esp_println::println;
 use esp_wifi::wifi::{ClientConfiguration, Configuration};
 use esp_wifi::wifi::{WifiController, WifiDevice, WifiEvent, WifiStaDevice, WifiState};
 use esp_wifi::{initialize, EspWifiInitFor};
-use hal::analog::dac::{set_dma_mode, DAC1, DAC2};
 use hal::clock::ClockControl;
+use hal::cpu_control::{CpuControl, Stack as CoreStack};
 use hal::dma::Dma;
+use hal::embassy::executor::Executor;
 use hal::gpio::{DriveStrength, IO};
 
 use hal::rng::Rng;
@@ -34,26 +35,60 @@ use hal::{embassy, peripherals::Peripherals, prelude::*, timer::TimerGroup};
 use static_cell::make_static;
 
 mod analog_clock_face;
+mod calibration;
+mod calibration_storage;
+#[cfg(feature = "circular-dma")]
+mod circular_dma;
+mod command;
+mod display_backend;
 mod font;
+mod frame_buffer;
+mod frame_exchange;
+mod home_assistant;
 mod httptest;
+mod intensity;
+#[cfg(not(feature = "mcp4922"))]
+mod internal_dac;
+mod limits;
+#[cfg(feature = "mcp4922")]
+mod mcp4922;
+mod message;
 mod mqtt;
+mod mqtt_client;
+mod mqtt_packet;
+mod night_mode;
 mod ntptime;
 mod picture;
 mod scopeclock;
+mod screensaver;
+mod settings;
+mod stall_watchdog;
+mod telemetry;
+mod test_pattern;
+mod test_pattern_drawing;
+mod text;
+mod tube_guard;
+mod webserver;
 
 use crate::httptest::http_stuff;
 use crate::mqtt::mqtt_stuff;
 use crate::ntptime::time_stuff;
 use crate::scopeclock::{scopeclock_init, scopeclock_task};
+use crate::telemetry::TELEMETRY;
+use crate::tube_guard::tube_guard_init;
+use crate::webserver::webserver_task;
 
 const SSID: &str = env!("SSID");
 const PASSWORD: &str = env!("PASSWORD");
 
+/// Stack of the APP CPU, which runs the display and the drawing task
+static mut APP_CORE_STACK: CoreStack<16384> = CoreStack::new();
+
 #[global_allocator]
 static ALLOCATOR: esp_alloc::EspHeap = esp_alloc::EspHeap::empty();
 
 fn init_heap() {
-    const HEAP_SIZE: usize = 8 * 1024;
+    const HEAP_SIZE: usize = 32 * 1024;
     static mut HEAP: MaybeUninit<[u8; HEAP_SIZE]> = MaybeUninit::uninit();
 
     unsafe {
@@ -75,7 +110,9 @@ async fn main(spawner: Spawner) -> ! {
     init_heap();
 
     #[cfg(target_arch = "xtensa")]
-    let timer = hal::timer::TimerGroup::new(peripherals.TIMG1, &clocks).timer0;
+    let timer_group1 = hal::timer::TimerGroup::new(peripherals.TIMG1, &clocks);
+    #[cfg(target_arch = "xtensa")]
+    let timer = timer_group1.timer0;
     #[cfg(target_arch = "riscv32")]
     let timer = hal::systimer::SystemTimer::new(peripherals.SYSTIMER).alarm0;
     let init = initialize(
@@ -102,32 +139,77 @@ async fn main(spawner: Spawner) -> ! {
     let stack = &*make_static!(Stack::new(
         wifi_interface,
         config,
-        make_static!(StackResources::<5>::new()),
+        make_static!(StackResources::<6>::new()),
         seed
     ));
 
     let io = IO::new(peripherals.GPIO, peripherals.IO_MUX);
-    let dac1_pin: hal::gpio::GpioPin<hal::gpio::Analog, 25> = io.pins.gpio25.into_analog();
-    let dac2_pin = io.pins.gpio26.into_analog();
     let mut z_blank = io.pins.gpio32.into_push_pull_output();
 
     z_blank.set_output_high(true);
     z_blank.set_drive_strength(DriveStrength::I5mA);
     z_blank.enable_output(true);
 
-    let mut _dac1 = DAC1::new(peripherals.DAC1, dac1_pin);
-    let mut _dac2 = DAC2::new(peripherals.DAC2, dac2_pin);
-    set_dma_mode(true);
+    #[cfg(feature = "analog-z")]
+    let intensity = intensity::ledc::LedcIntensity::new(
+        peripherals.LEDC,
+        io.pins.gpio33.into_push_pull_output(),
+        &clocks,
+    );
+    #[cfg(not(feature = "analog-z"))]
+    let intensity = intensity::NoIntensity;
 
     let dma = Dma::new(peripherals.DMA);
-    let dma_channel = dma.i2s0channel;
-    let i2s = peripherals.I2S0;
 
-    let static_part_meta = scopeclock_init(i2s, &clocks, dma_channel, z_blank, delay);
+    #[cfg(not(feature = "mcp4922"))]
+    let backend_resources = internal_dac::InternalDacResources {
+        i2s: peripherals.I2S0,
+        dma_channel: dma.i2s0channel,
+        dac1: peripherals.DAC1,
+        dac2: peripherals.DAC2,
+        dac1_pin: io.pins.gpio25.into_analog(),
+        dac2_pin: io.pins.gpio26.into_analog(),
+    };
+    #[cfg(feature = "mcp4922")]
+    let backend_resources = mcp4922::Mcp4922Resources {
+        i2s: peripherals.I2S1,
+        dma_channel: dma.i2s1channel,
+        sck: io.pins.gpio18,
+        cs: io.pins.gpio5,
+        sdi: io.pins.gpio23,
+    };
+
+    // Before the display runs, as reading the flash stops the cache
+    calibration_storage::load();
+
+    // The display runs on the APP CPU with its own executor. Its interrupts
+    // are not delayed by the WiFi and the network stack on the PRO CPU.
+    let clocks = &clocks;
+    let software_interrupt = system.software_interrupt_control;
+    let display_core = move || {
+        let renderer = scopeclock_init(
+            backend_resources,
+            clocks,
+            z_blank,
+            intensity,
+            delay,
+            software_interrupt,
+        );
+        let executor = make_static!(Executor::new());
+        executor.run(|spawner| {
+            spawner.spawn(scopeclock_task(renderer)).ok();
+        });
+    };
+    let mut cpu_control = CpuControl::new(system.cpu_control);
+    let _display_core = cpu_control
+        .start_app_core(unsafe { &mut APP_CORE_STACK }, display_core)
+        .unwrap();
+
+    // Stays on the PRO CPU to still protect the tube if the APP CPU hangs
+    tube_guard_init(timer_group1.timer1);
 
     spawner.spawn(connection(controller)).ok();
     spawner.spawn(net_task(stack)).ok();
-    spawner.spawn(scopeclock_task(static_part_meta)).ok();
 
     loop {
         if stack.is_link_up() {
@@ -147,12 +229,15 @@ async fn main(spawner: Spawner) -> ! {
 
     //spawner.spawn(http_stuff(stack)).ok();
     spawner.spawn(time_stuff(stack)).ok();
-    //spawner.spawn(mqtt_stuff(stack)).ok();
+    if let Some(broker) = mqtt::BROKER {
+        spawner.spawn(mqtt_stuff(stack, broker)).ok();
+    }
+    spawner.spawn(webserver_task(stack)).ok();
 
     // endless loop
     loop {
         Timer::after(Duration::from_millis(5000)).await;
-        //println!("Still alive...");
+        println!("{}", TELEMETRY.snapshot());
     }
 }
 
@@ -0,0 +1,167 @@
+#[path = "util.rs"]
+mod examples_util;
+
+use esp_hal::i2s::{DataFormat, I2s, I2sTx, I2sWriteDma, Standard};
+use examples_util::hal;
+use hal::clock::Clocks;
+use hal::delay::Delay;
+use hal::dma::{DmaPriority, I2s1DmaChannel, I2s1DmaChannelCreator};
+use hal::gpio::{GpioPin, Unknown};
+use hal::peripherals::{Interrupt, I2S1};
+use hal::prelude::*;
+use hal::Blocking;
+
+use crate::display_backend::{DisplayBackend, I2sClock, I2sInterrupts, UnsupportedRate};
+use crate::limits;
+
+/// Peripherals and pins connected to the MCP4922.
+/// LDAC of the MCP4922 is tied to ground, so every word is output when it was received.
+pub struct Mcp4922Resources {
+    pub i2s: I2S1,
+    pub dma_channel: I2s1DmaChannelCreator,
+    /// SCK of the DAC
+    pub sck: GpioPin<Unknown, 18>,
+    /// CS of the DAC
+    pub cs: GpioPin<Unknown, 5>,
+    /// SDI of the DAC
+    pub sdi: GpioPin<Unknown, 23>,
+}
+
+/// Word written to DAC A (X). Unbuffered reference, 1x gain, output enabled.
+const WORD_A: u16 = 0x3000;
+/// Word written to DAC B (Y)
+const WORD_B: u16 = 0x8000 | WORD_A;
+
+/// Bits of the right channel which carry the blanking of the beam.
+/// All of them are set to blank the beam.
+const Z_BLANK_BITS: u16 = 0xfffe;
+
+/// External 12 bit dual DAC MCP4922, or the 8 bit MCP4902 with the lowest 4 bits ignored.
+///
+/// The MCP4922 latches a word on the rising edge of CS, so CS must toggle after
+/// every 16 bits. The SPI units of the ESP32 keep CS low for a complete DMA transfer,
+/// which would require one transfer per word. I2S1 is used as serial interface instead.
+/// BCK is the clock and WS is the chip select, which is low for the left channel
+/// and high for the right channel. Every left channel carries one word for the DAC,
+/// the right channel is ignored by the DAC as CS is high.
+///
+/// I2S in Philips format changes WS one clock before the MSB, so the DAC receives
+/// the LSB of the previous right channel followed by the upper 15 bits of the left
+/// channel. The words are shifted by one bit to compensate.
+///
+/// A sample therefore consists of 2 I2S frames, one for each DAC.
+///
+/// The remaining bits of the right channels carry the blanking of the beam.
+/// With the feature `mcp4922-z`, a 74HC595 with SER on SD, SRCLK on BCK and
+/// RCLK on the inverted WS latches them at the start of every frame. Its output QA
+/// is combined with the Z blanking of GPIO 32, which then only blanks between frames.
+pub struct Mcp4922 {
+    tx: I2sTx<'static, I2S1, I2s1DmaChannel, Blocking>,
+    i2s_interrupts: I2sInterrupts,
+    clock: I2sClock,
+}
+
+/// Writes an I2S frame. The ESP32 sends the half word at the higher address first.
+fn write_frame(frame: &mut [u8], left: u16, right: u16) {
+    frame[0..2].copy_from_slice(&right.to_le_bytes());
+    frame[2..4].copy_from_slice(&left.to_le_bytes());
+}
+
+fn read_left(frame: &[u8]) -> u16 {
+    u16::from_le_bytes([frame[2], frame[3]])
+}
+
+impl DisplayBackend for Mcp4922 {
+    type Resources = Mcp4922Resources;
+
+    const RESOLUTION_BITS: u32 = 12;
+    const SAMPLE_BYTES: usize = 8;
+    /// The DAC needs about 4.5µs to settle after a full scale step.
+    /// Also limited by the setup time of CS before the clock, which is
+    /// half a period of BCK with 32 BCK per I2S frame.
+    const SAMPLE_RATE: u32 = 150_000;
+    const INTERRUPT: Interrupt = Interrupt::I2S1;
+    const I2S_BASE: u32 = 0x3ff6d000;
+    const BLANK_IN_SAMPLE: bool = cfg!(feature = "mcp4922-z");
+
+    fn new(
+        resources: Mcp4922Resources,
+        tx_descriptors: &'static mut [u32],
+        rx_descriptors: &'static mut [u32],
+        clocks: &Clocks,
+    ) -> Self {
+        let i2s_interrupts = I2sInterrupts::new(&resources.i2s);
+        let i2s = I2s::new(
+            resources.i2s,
+            Standard::Philips,
+            DataFormat::Data16Channel16,
+            // One I2S frame per channel
+            (Self::SAMPLE_RATE * limits::I2S_FRAMES_PER_SAMPLE).Hz(),
+            resources.dma_channel.configure(
+                false,
+                tx_descriptors,
+                rx_descriptors,
+                DmaPriority::Priority0,
+            ),
+            clocks,
+        );
+
+        let tx = i2s
+            .i2s_tx
+            .with_bclk(resources.sck)
+            .with_ws(resources.cs)
+            .with_dout(resources.sdi)
+            .build();
+
+        Self {
+            tx,
+            i2s_interrupts,
+            // Both rates are in samples, which are 2 I2S frames each
+            clock: I2sClock::capture(Self::I2S_BASE, Self::SAMPLE_RATE),
+        }
+    }
+
+    fn encode(x: u16, y: u16, beam_on: bool, sample: &mut [u8]) {
+        let word_a = WORD_A | (x & 0xfff);
+        let word_b = WORD_B | (y & 0xfff);
+        let blank = if beam_on { 0 } else { Z_BLANK_BITS };
+        // The LSB of the right channel provides the MSB of the next word
+        write_frame(&mut sample[0..4], word_a << 1, blank | (word_b >> 15));
+        write_frame(&mut sample[4..8], word_b << 1, blank | (WORD_A >> 15));
+    }
+
+    fn decode(sample: &[u8]) -> (u16, u16) {
+        let x = (read_left(&sample[0..4]) >> 1) & 0xfff;
+        let y = (read_left(&sample[4..8]) >> 1) & 0xfff;
+        (x, y)
+    }
+
+    #[ram]
+    fn write_blocking(&mut self, samples: &[u8], delay: &Delay) {
+        let transfer = self.tx.write_dma(&samples).unwrap();
+        // delay to give the DMA some time to activate
+        delay.delay_nanos(10);
+        transfer.wait().unwrap();
+    }
+
+    #[ram]
+    unsafe fn start_write(&mut self, samples: &[u8]) {
+        let transfer = self.tx.write_dma(&samples).unwrap();
+        // Dropping the transfer would wait for its end. It can't be kept either, as it
+        // borrows the samples and the I2S. The caller guarantees that the samples outlive
+        // the DMA and that the I2S isn't used before the end.
+        core::mem::forget(transfer);
+    }
+
+    fn set_sample_rate(&mut self, rate: u32) -> Result<(), UnsupportedRate> {
+        self.clock.set_rate(rate)
+    }
+
+    fn clear_tx_rempty(&self) {
+        self.i2s_interrupts.clear_tx_rempty();
+    }
+
+    fn enable_tx_rempty(&self) {
+        self.i2s_interrupts.enable_tx_rempty();
+    }
+}
@@ -0,0 +1,153 @@
+//! Short text messages pushed over the network.
+//!
+//! A message replaces the clock face until it expires. Up to MAX_LINES lines
+//! are shown as a banner in the middle of the screen. Longer messages scroll
+//! through a single line from right to left.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use critical_section::Mutex;
+use embassy_time::{Duration, Instant};
+
+use crate::analog_clock_face::GLOBAL_SCALE;
+use crate::limits;
+use crate::picture::{Picture, MAX_COORDINATE};
+use crate::screensaver::Appearance;
+use crate::text::{self, PlacedGlyph, CAP_HEIGHT};
+
+/// Seconds a message is shown if not replaced before
+pub static TIMEOUT: AtomicU32 = AtomicU32::new(60);
+
+/// Lines of a banner. Messages requiring more are scrolled.
+const MAX_LINES: usize = 3;
+
+/// Size of a grid unit of the font in logical coordinates
+const SCALE: f32 = 3.5 * GLOBAL_SCALE as f32;
+
+/// Distance between the baselines of a banner in grid units
+const LINE_PITCH: i32 = CAP_HEIGHT + 4;
+
+/// Center of the clock face
+const CENTER: isize = 0x82 * GLOBAL_SCALE;
+
+/// Text is kept inside this distance to the border of the DAC range
+const MARGIN: isize = 30 * GLOBAL_SCALE;
+
+/// Speed of scrolling text in logical units per second
+const SCROLL_SPEED: f32 = 60.0 * GLOBAL_SCALE as f32;
+
+/// Length of strokes in logical units which fit into a frame at normal brightness.
+/// Longer texts are drawn darker.
+const STROKE_BUDGET: f32 = 3000.0;
+
+struct Message {
+    text: String,
+    shown_since: Instant,
+    expires: Instant,
+}
+
+static MESSAGE: Mutex<RefCell<Option<Message>>> = Mutex::new(RefCell::new(None));
+
+#[derive(Debug)]
+pub struct TooLong;
+
+/// Shows a message for TIMEOUT seconds. An empty text removes the current message.
+pub fn show(text: &str) -> Result<(), TooLong> {
+    if text.len() > limits::MESSAGE_LENGTH {
+        return Err(TooLong);
+    }
+    let now = Instant::now();
+    let timeout = Duration::from_secs(TIMEOUT.load(Ordering::Relaxed) as u64);
+    let message = (!text.is_empty()).then(|| Message {
+        text: String::from(text),
+        shown_since: now,
+        expires: now + timeout,
+    });
+    critical_section::with(|cs| *MESSAGE.borrow_ref_mut(cs) = message);
+    Ok(())
+}
+
+/// Text of the message and the time it is shown already. None if there is none or it expired.
+pub fn current() -> Option<(String, Duration)> {
+    critical_section::with(|cs| {
+        let mut message = MESSAGE.borrow_ref_mut(cs);
+        let now = Instant::now();
+        if message.as_ref().is_some_and(|m| now >= m.expires) {
+            *message = None;
+        }
+        message
+            .as_ref()
+            .map(|m| (m.text.clone(), now - m.shown_since))
+    })
+}
+
+/// Dims the picture so that the glyphs fit into the frame, then draws them
+fn draw_glyphs(pic: &mut Picture, glyphs: &[PlacedGlyph], appearance: &Appearance) {
+    let length: f32 = glyphs.iter().map(|g| g.length()).sum();
+    pic.dim(appearance.dim_factor.max(length / STROKE_BUDGET));
+    for glyph in glyphs {
+        glyph.draw(pic);
+    }
+}
+
+/// Lines centered horizontally and the whole block centered vertically
+fn draw_banner(pic: &mut Picture, lines: &[&str], appearance: &Appearance) {
+    let height = (lines.len() as i32 - 1) * LINE_PITCH + CAP_HEIGHT;
+    let top = CENTER + libm::roundf(height as f32 * SCALE / 2.0) as isize;
+    let mut glyphs = Vec::new();
+    for (index, line) in lines.iter().enumerate() {
+        let width = libm::roundf(text::width(line) as f32 * SCALE) as isize;
+        let baseline =
+            top - libm::roundf(((index as i32 * LINE_PITCH + CAP_HEIGHT) as f32) * SCALE) as isize;
+        glyphs.extend(text::place(line, (CENTER - width / 2, baseline), SCALE));
+    }
+    draw_glyphs(pic, &glyphs, appearance);
+}
+
+/// Moves the text as single line through the visible area.
+/// Only glyphs which are completely visible are drawn.
+fn draw_scrolling(pic: &mut Picture, text: &str, shown: Duration, appearance: &Appearance) {
+    let line: String = text
+        .chars()
+        .map(|c| if c == '\n' { ' ' } else { c })
+        .collect();
+    let width = libm::roundf(text::width(&line) as f32 * SCALE) as isize;
+    let (left, right) = (MARGIN, MAX_COORDINATE as isize - MARGIN);
+
+    // Enters at the right side and restarts after leaving at the left side
+    let travel = (width + right - left) as u64;
+    let moved = (shown.as_millis() as f32 * SCROLL_SPEED / 1000.0) as u64 % travel;
+    let baseline = CENTER - libm::roundf(CAP_HEIGHT as f32 * SCALE / 2.0) as isize;
+    let origin = (right - moved as isize, baseline);
+
+    let glyphs: Vec<PlacedGlyph> = text::place(&line, origin, SCALE)
+        .filter(|glyph| {
+            let (start, end) = glyph.span();
+            start >= left && end <= right
+        })
+        .collect();
+    draw_glyphs(pic, &glyphs, appearance);
+}
+
+pub fn draw_message<'a>(
+    tx_buffer: &'a mut [u8],
+    text: &str,
+    shown: Duration,
+    appearance: &Appearance,
+) -> Picture<'a> {
+    let mut pic = Picture::new(tx_buffer);
+    pic.transform = appearance.transform;
+
+    let max_width = ((MAX_COORDINATE as isize - 2 * MARGIN) as f32 / SCALE) as i32;
+    let lines = text::wrap(text, max_width);
+    if lines.len() <= MAX_LINES {
+        draw_banner(&mut pic, &lines, appearance);
+    } else {
+        draw_scrolling(&mut pic, text, shown, appearance);
+    }
+    pic.frame.optimize_path(0);
+    pic
+}
@@ -1,161 +1,175 @@
+//! Remote control and telemetry over MQTT.
+//!
+//! Only active if the host name of the broker is given at build time with MQTT_BROKER.
+//! The connection is restored with an increasing delay if it is lost.
+
+use alloc::format;
+use alloc::string::String;
+
 use embassy_net::tcp::TcpSocket;
 
 use embassy_net::Stack;
 
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
 use esp_backtrace as _;
 
 use esp_println::println;
 
 use esp_wifi::wifi::{WifiDevice, WifiStaDevice};
 
-use embassy_futures::select::{select, Either};
-use rust_mqtt::{
-    client::{client::MqttClient, client_config::ClientConfig},
-    packet::v5::reason_codes::ReasonCode,
-    utils::rng_generator::CountingRng,
-};
 use smoltcp::wire::DnsQueryType;
 
-use crate::scopeclock;
+use crate::mqtt_client::{self, Error, Options, Session, Will};
+use crate::telemetry::DeviceStatus;
+use crate::{home_assistant, settings};
+
+/// Host name of the broker
+pub const BROKER: Option<&str> = option_env!("MQTT_BROKER");
+const USERNAME: Option<&str> = option_env!("MQTT_USER");
+const PASSWORD: Option<&str> = option_env!("MQTT_PASSWORD");
+const PORT: u16 = 1883;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const TELEMETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Retained on the availability topic while connected.
+/// Replaced by the broker with OFFLINE if the connection is lost.
+const ONLINE: &[u8] = b"online";
+const OFFLINE: &[u8] = b"offline";
+
+/// Delay before the first reconnect. Doubled after every failed attempt.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Unique name of this clock, derived from the MAC address
+pub fn device_id() -> String {
+    let mac = esp_hal::efuse::Efuse::get_mac_address();
+    format!("scopeclock-{:02x}{:02x}{:02x}", mac[3], mac[4], mac[5])
+}
+
+/// Topic of this clock, e.g. "scopeclock-a1b2c3/status"
+pub fn topic(device_id: &str, name: &str) -> String {
+    format!("{}/{}", device_id, name)
+}
+
+/// Topic receiving a command, e.g. "scopeclock-a1b2c3/set/brightness"
+pub fn command_topic(device_id: &str, command: &str) -> String {
+    format!("{}/set/{}", device_id, command)
+}
+
+/// Executes a command and reports the outcome on the status topic
+fn handle_message(device_id: &str, topic: &str, payload: &[u8]) {
+    let prefix = command_topic(device_id, "");
+    let Some(name) = topic.strip_prefix(prefix.as_str()) else {
+        println!("Unexpected topic {}: {:?}", topic, payload);
+        return;
+    };
+    let status = match settings::run(name, payload) {
+        Ok(()) => format!("{}: ok", name),
+        Err(e) => format!("{}: {}", name, e),
+    };
+    let status_topic = self::topic(device_id, "status");
+    if mqtt_client::publish(&status_topic, status.as_bytes(), false, false).is_err() {
+        println!("MQTT queue full, status dropped");
+    }
+}
 
 #[embassy_executor::task]
-pub async fn mqtt_stuff(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
+pub async fn mqtt_stuff(
+    stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
+    broker: &'static str,
+) {
     let mut rx_buffer = [0; 1000];
     let mut tx_buffer = [0; 1000];
+    let client_id = device_id();
+    let availability = topic(&client_id, "availability");
+    let options = Options {
+        client_id: &client_id,
+        username: USERNAME,
+        password: PASSWORD,
+        keep_alive: KEEP_ALIVE,
+        will: Some(Will {
+            topic: &availability,
+            payload: OFFLINE,
+            retain: true,
+        }),
+    };
+    let mut backoff = MIN_BACKOFF;
     loop {
-        Timer::after(Duration::from_millis(1_000)).await;
-
         let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
-
-        socket.set_timeout(Some(embassy_time::Duration::from_secs(10)));
-
-        let address = match stack
-            .dns_query("slamy", DnsQueryType::A)
-            .await
-            .map(|a| a[0])
-        {
-            Ok(address) => address,
-            Err(e) => {
-                println!("DNS lookup error: {e:?}");
-                continue;
+        socket.set_timeout(Some(Duration::from_secs(10)));
+
+        match connect(stack, socket, broker, &options).await {
+            Ok(mut session) => {
+                println!("MQTT connected!");
+                backoff = MIN_BACKOFF;
+                let result = serve(&mut session, &client_id).await;
+                println!("MQTT connection lost: {:?}", result);
+                session.abandon();
             }
-        };
-
-        //let address = Ipv4Address([192, 168, 178, 32]);
-        let remote_endpoint = (address, 1883);
-        println!("MQTT connecting to {:?}", remote_endpoint);
-        let connection = socket.connect(remote_endpoint).await;
-        if let Err(e) = connection {
-            println!("MQTT connect error: {:?}", e);
-            continue;
-        }
-        println!("MQTT connected!");
-
-        let mut config = ClientConfig::new(
-            rust_mqtt::client::client_config::MqttVersion::MQTTv5,
-            CountingRng(20000),
-        );
-        config.add_max_subscribe_qos(rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS1);
-
-        config.add_client_id("clientId-8rhWgBODCl");
-        config.max_packet_size = 100;
-        let mut recv_buffer = [0; 80];
-        let mut write_buffer = [0; 80];
-
-        let mut client =
-            MqttClient::<_, 5, _>::new(socket, &mut write_buffer, 80, &mut recv_buffer, 80, config);
-
-        match client.connect_to_broker().await {
-            Ok(()) => {}
-            Err(mqtt_error) => match mqtt_error {
-                ReasonCode::NetworkError => {
-                    println!("MQTT Network Error");
-                    continue;
-                }
-                _ => {
-                    println!("Other MQTT Error: {:?}", mqtt_error);
-                    continue;
-                }
-            },
+            Err(e) => println!("MQTT connect error: {:?}", e),
         }
 
-        client.subscribe_to_topic("beam_off").await.unwrap();
-        client.subscribe_to_topic("beam_on").await.unwrap();
-
-        loop {
-            // TODO There is a big issue here. rust-mqtt by obabec is flawed
-            // https://github.com/obabec/rust-mqtt/issues/38
-            // https://github.com/obabec/rust-mqtt/issues/23
-            // workaround inspired by https://github.com/obabec/rust-mqtt/issues/36
-            // The workaround is still not helping and data might get lost.
-            // It only helps keeping the connection alive as a ping is sent.
-            match select(
-                client.receive_message(),
-                Timer::after(Duration::from_secs(2)),
-            )
-            .await
-            {
-                Either::First(msg) => match msg {
-                    Ok(("beam_off", param)) => {
-                        if let Ok(s) = core::str::from_utf8(param) {
-                            let p = s.parse::<u32>().unwrap();
-                            println!("Beam off: {}", p);
-                            scopeclock::WAIT_BEFORE_BEAM_OFF
-                                .store(p, core::sync::atomic::Ordering::Relaxed)
-                        }
-                    }
-                    Ok(("beam_on", param)) => {
-                        if let Ok(s) = core::str::from_utf8(param) {
-                            let p = s.parse::<u32>().unwrap();
-                            println!("Beam on: {}", p);
-                            scopeclock::WAIT_AFTER_BEAM_ON
-                                .store(p, core::sync::atomic::Ordering::Relaxed)
-                        }
-                    }
-                    Ok((topic, param)) => {
-                        println!("Unexpected topic {}: {:?}", topic, param);
-                    }
-                    Err(mqtt_error) => {
-                        println!("Other MQTT Error: {:?}", mqtt_error);
-                    }
-                },
-                Either::Second(_timeout) => {
-                    client.send_ping().await;
-                }
-            }
+        println!("MQTT reconnect in {}s", backoff.as_secs());
+        Timer::after(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn connect<'s>(
+    stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>,
+    mut socket: TcpSocket<'s>,
+    broker: &str,
+    options: &Options<'_>,
+) -> Result<Session<'s>, Error> {
+    let address = stack
+        .dns_query(broker, DnsQueryType::A)
+        .await
+        .map_err(|_| Error::Dns)?
+        .first()
+        .copied()
+        .ok_or(Error::Dns)?;
+
+    let remote_endpoint = (address, PORT);
+    println!("MQTT connecting to {:?}", remote_endpoint);
+    socket
+        .connect(remote_endpoint)
+        .await
+        .map_err(Error::Connect)?;
+    Session::connect(socket, options).await
+}
 
-            Timer::after(Duration::from_millis(100)).await;
-
-            /*
-            // Convert temperature into String
-            let temperature_string = format!("Howdy {num}");
-            num += 1;
-            match client
-                .send_message(
-                    "temperature/1",
-                    temperature_string.as_bytes(),
-                    rust_mqtt::packet::v5::publish_packet::QualityOfService::QoS1,
-                    true,
-                )
-                .await
-            {
-                Ok(()) => {}
-                Err(mqtt_error) => match mqtt_error {
-                    ReasonCode::NetworkError => {
-                        println!("MQTT Network Error");
-                    }
-                    ReasonCode::NoMatchingSubscribers => {
-                        // Just ignore that
-                    }
-                    _ => {
-                        println!("Other MQTT Error: {:?}", mqtt_error);
-                    }
-                },
+/// Runs until the connection fails
+async fn serve(session: &mut Session<'_>, device_id: &str) -> Error {
+    if let Err(e) = session.subscribe(&[&command_topic(device_id, "+")]).await {
+        return e;
+    }
+    if mqtt_client::publish(&topic(device_id, "availability"), ONLINE, true, true).is_err() {
+        println!("MQTT queue full, availability dropped");
+    }
+    if home_assistant::publish_discovery(device_id).is_err() {
+        println!("MQTT queue full, discovery incomplete");
+    }
+    let telemetry_topic = topic(device_id, "telemetry");
+    let state_topic = topic(device_id, "state");
+    let mut next_telemetry = Instant::now();
+    loop {
+        if Instant::now() >= next_telemetry {
+            next_telemetry += TELEMETRY_INTERVAL;
+            let telemetry = format!("{}", DeviceStatus::capture());
+            if mqtt_client::publish(&telemetry_topic, telemetry.as_bytes(), true, false).is_err() {
+                println!("MQTT queue full, telemetry dropped");
+            }
+            let state = home_assistant::state_json();
+            if mqtt_client::publish(&state_topic, state.as_bytes(), false, false).is_err() {
+                println!("MQTT queue full, state dropped");
             }
-            Timer::after(Duration::from_millis(3000)).await;
+        }
 
-             */
+        match session.poll().await {
+            Ok(Some(message)) => handle_message(device_id, message.topic, message.payload),
+            Ok(None) => {}
+            Err(e) => return e,
         }
     }
 }
@@ -0,0 +1,304 @@
+//! Small MQTT 3.1.1 client on top of a TCP socket.
+//!
+//! Only what the clock needs: QoS 0 and 1 in both directions, retained messages,
+//! a last will and the keep alive. Received bytes are collected in a buffer first,
+//! so waiting for data can be interrupted at any time without losing a packet.
+//!
+//! Messages to publish are queued and sent by the task owning the session.
+//! A QoS 1 message which wasn't acknowledged before the connection was lost
+//! is queued again and sent with the next session.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::ops::Range;
+
+use critical_section::Mutex;
+use embassy_net::tcp::{self, TcpSocket};
+use embassy_time::{with_timeout, Duration, Instant};
+use embedded_io_async::Write;
+
+use crate::mqtt_packet::{
+    connack_code, connect_packet, enqueue, parse_publish, puback_packet, publish_packet, read_u16,
+    suback_failed, subscribe_packet, Connect, Malformed, CONNACK, PINGREQ, PINGRESP, PUBACK,
+    PUBLISH, SUBACK,
+};
+pub use crate::mqtt_packet::{Outgoing, QueueFull, Will};
+
+/// Size of the buffer for received packets. Larger packets end the connection.
+const RX_BUFFER_SIZE: usize = 1024;
+
+/// Messages waiting to be published. Further messages are rejected.
+const QUEUE_LENGTH: usize = 16;
+
+/// Time to wait for data before the keep alive and the queue are handled again
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Time the broker has to answer CONNECT and to acknowledge a QoS 1 message
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum Error {
+    /// The host name of the broker couldn't be resolved
+    Dns,
+    Connect(tcp::ConnectError),
+    Network(tcp::Error),
+    /// The broker closed the connection
+    Closed,
+    /// Malformed, unexpected or too large packet
+    Protocol,
+    /// The broker refused the connection with this return code
+    Refused(u8),
+    /// The broker rejected a subscription
+    SubscriptionFailed,
+    /// The broker didn't answer in time
+    Timeout,
+}
+
+impl From<tcp::Error> for Error {
+    fn from(error: tcp::Error) -> Self {
+        Error::Network(error)
+    }
+}
+
+impl From<Malformed> for Error {
+    fn from(_: Malformed) -> Self {
+        Error::Protocol
+    }
+}
+
+static QUEUE: Mutex<RefCell<heapless::Deque<Outgoing, QUEUE_LENGTH>>> =
+    Mutex::new(RefCell::new(heapless::Deque::new()));
+
+/// Queues a message to be sent by the MQTT task. Can be called from anywhere.
+/// Messages are kept while there is no connection. A retained message replaces
+/// a queued one of the same topic.
+pub fn publish(
+    topic: &str,
+    payload: &[u8],
+    retain: bool,
+    acknowledged: bool,
+) -> Result<(), QueueFull> {
+    let message = Outgoing {
+        topic: String::from(topic),
+        payload: Vec::from(payload),
+        retain,
+        acknowledged,
+    };
+    critical_section::with(|cs| enqueue(&mut QUEUE.borrow_ref_mut(cs), message))
+}
+
+fn next_queued() -> Option<Outgoing> {
+    critical_section::with(|cs| QUEUE.borrow_ref_mut(cs).pop_front())
+}
+
+pub struct Options<'a> {
+    pub client_id: &'a str,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    /// The broker considers the client dead after 1.5 times of this without a packet
+    pub keep_alive: Duration,
+    pub will: Option<Will<'a>>,
+}
+
+/// Message from the broker. Valid until the next poll.
+pub struct Received<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+}
+
+/// Connection to the broker
+pub struct Session<'s> {
+    socket: TcpSocket<'s>,
+    rx: Vec<u8>,
+    /// Received bytes in rx
+    rx_len: usize,
+    /// Bytes of the packet handed out last. Removed when the next one is received.
+    consumed: usize,
+    keep_alive: Duration,
+    last_sent: Instant,
+    ping_sent: Option<Instant>,
+    last_packet_id: u16,
+    /// QoS 1 message waiting for the acknowledge with its packet id and when it was sent
+    inflight: Option<(u16, Outgoing, Instant)>,
+}
+
+impl<'s> Session<'s> {
+    /// Logs in to the broker using a connected socket
+    pub async fn connect(
+        socket: TcpSocket<'s>,
+        options: &Options<'_>,
+    ) -> Result<Session<'s>, Error> {
+        let mut session = Session {
+            socket,
+            rx: alloc::vec![0; RX_BUFFER_SIZE],
+            rx_len: 0,
+            consumed: 0,
+            keep_alive: options.keep_alive,
+            last_sent: Instant::now(),
+            ping_sent: None,
+            last_packet_id: 0,
+            inflight: None,
+        };
+        let connect = Connect {
+            client_id: options.client_id,
+            username: options.username,
+            password: options.password,
+            keep_alive_secs: options.keep_alive.as_secs() as u16,
+            will: options.will.as_ref(),
+        };
+        session.send(&connect_packet(&connect)).await?;
+
+        let Some((header, body)) = session.receive(ACK_TIMEOUT).await? else {
+            return Err(Error::Timeout);
+        };
+        if header != CONNACK {
+            return Err(Error::Protocol);
+        }
+        match connack_code(&session.rx[body])? {
+            0 => Ok(session),
+            code => Err(Error::Refused(code)),
+        }
+    }
+
+    /// Requests messages of the topics with QoS 1.
+    /// A rejection by the broker is reported by a later poll.
+    pub async fn subscribe(&mut self, topics: &[&str]) -> Result<(), Error> {
+        let id = self.packet_id();
+        self.send(&subscribe_packet(id, topics)).await
+    }
+
+    /// Handles the connection for a short time and returns a message if one was received.
+    /// Sends the queued messages and keeps the connection alive.
+    /// Any error means the connection is unusable.
+    pub async fn poll(&mut self) -> Result<Option<Received<'_>>, Error> {
+        self.send_queued().await?;
+        self.check_keep_alive().await?;
+
+        let Some((header, body)) = self.receive(POLL_INTERVAL).await? else {
+            return Ok(None);
+        };
+        match header & 0xf0 {
+            PUBLISH => self.received_publish(header, body).await,
+            PUBACK => {
+                let id = read_u16(&self.rx[body], 0)?;
+                if matches!(self.inflight, Some((inflight, _, _)) if inflight == id) {
+                    self.inflight = None;
+                }
+                Ok(None)
+            }
+            SUBACK => match suback_failed(&self.rx[body])? {
+                true => Err(Error::SubscriptionFailed),
+                false => Ok(None),
+            },
+            PINGRESP => {
+                self.ping_sent = None;
+                Ok(None)
+            }
+            _ => Err(Error::Protocol),
+        }
+    }
+
+    /// Ends the session. An unacknowledged message is queued again for the next session.
+    pub fn abandon(mut self) {
+        if let Some((_, message, _)) = self.inflight.take() {
+            critical_section::with(|cs| QUEUE.borrow_ref_mut(cs).push_front(message)).ok();
+        }
+        self.socket.abort();
+    }
+
+    async fn received_publish(
+        &mut self,
+        header: u8,
+        body: Range<usize>,
+    ) -> Result<Option<Received<'_>>, Error> {
+        let publish = parse_publish(header, &self.rx[body.clone()])?;
+        if let Some(id) = publish.packet_id {
+            self.send(&puback_packet(id)).await?;
+        }
+
+        let data = &self.rx[body];
+        // Checked by parse_publish
+        let topic = core::str::from_utf8(&data[publish.topic]).map_err(|_| Error::Protocol)?;
+        Ok(Some(Received {
+            topic,
+            payload: &data[publish.payload],
+        }))
+    }
+
+    /// QoS 1 messages are sent one after the other to keep their order
+    async fn send_queued(&mut self) -> Result<(), Error> {
+        while self.inflight.is_none() {
+            let Some(message) = next_queued() else {
+                break;
+            };
+            if message.acknowledged {
+                let id = self.packet_id();
+                let packet = publish_packet(&message, Some(id));
+                // Before sending, so the message is queued again if sending fails
+                self.inflight = Some((id, message, Instant::now()));
+                self.send(&packet).await?;
+            } else {
+                self.send(&publish_packet(&message, None)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn check_keep_alive(&mut self) -> Result<(), Error> {
+        if let Some((_, _, sent)) = &self.inflight {
+            if sent.elapsed() > ACK_TIMEOUT {
+                return Err(Error::Timeout);
+            }
+        }
+        match self.ping_sent {
+            Some(sent) if sent.elapsed() > ACK_TIMEOUT => Err(Error::Timeout),
+            Some(_) => Ok(()),
+            // Ping early enough to get the answer before the broker gives up
+            None if self.last_sent.elapsed() >= self.keep_alive / 2 => {
+                self.send(&[PINGREQ, 0]).await?;
+                self.ping_sent = Some(Instant::now());
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn packet_id(&mut self) -> u16 {
+        // 0 is not allowed
+        self.last_packet_id = self.last_packet_id.checked_add(1).unwrap_or(1);
+        self.last_packet_id
+    }
+
+    async fn send(&mut self, packet: &[u8]) -> Result<(), Error> {
+        self.socket.write_all(packet).await?;
+        self.socket.flush().await?;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Waits up to the timeout for the next complete packet.
+    /// Returns the first byte of the fixed header and the range of the remaining bytes in rx.
+    async fn receive(&mut self, timeout: Duration) -> Result<Option<(u8, Range<usize>)>, Error> {
+        // Drop the packet handed out before
+        self.rx.copy_within(self.consumed..self.rx_len, 0);
+        self.rx_len -= self.consumed;
+        self.consumed = 0;
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some((header, body)) = complete_packet(&self.rx[..self.rx_len], RX_BUFFER_SIZE)?
+            {
+                self.consumed = body.end;
+                return Ok(Some((header, body)));
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match with_timeout(remaining, self.socket.read(&mut self.rx[self.rx_len..])).await {
+                Err(_) => return Ok(None),
+                Ok(Ok(0)) => return Err(Error::Closed),
+                Ok(Ok(n)) => self.rx_len += n,
+                Ok(Err(e)) => return Err(e.into()),
+            }
+        }
+    }
+}
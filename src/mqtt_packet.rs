@@ -0,0 +1,524 @@
+//! Packets and queued messages of the MQTT client.
+//!
+//! Independent of the network, so the encoding can be checked on the host,
+//! also against a real broker.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+pub const CONNECT: u8 = 0x10;
+pub const CONNACK: u8 = 0x20;
+pub const PUBLISH: u8 = 0x30;
+pub const PUBACK: u8 = 0x40;
+pub const SUBSCRIBE: u8 = 0x82;
+pub const SUBACK: u8 = 0x90;
+pub const PINGREQ: u8 = 0xc0;
+pub const PINGRESP: u8 = 0xd0;
+
+const CONNECT_CLEAN_SESSION: u8 = 0x02;
+const CONNECT_WILL: u8 = 0x04;
+const CONNECT_WILL_RETAIN: u8 = 0x20;
+const CONNECT_PASSWORD: u8 = 0x40;
+const CONNECT_USERNAME: u8 = 0x80;
+
+const PUBLISH_RETAIN: u8 = 0x01;
+const PUBLISH_QOS1: u8 = 0x02;
+
+/// Return code of SUBACK for a rejected subscription
+const SUBACK_FAILURE: u8 = 0x80;
+
+/// The packet doesn't follow the protocol or is too large
+#[derive(Debug, PartialEq)]
+pub struct Malformed;
+
+#[derive(Debug, PartialEq)]
+pub struct QueueFull;
+
+/// Message to be published
+#[derive(Debug, PartialEq)]
+pub struct Outgoing {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub retain: bool,
+    /// Published with QoS 1 and repeated until the broker acknowledges it.
+    /// QoS 0 otherwise.
+    pub acknowledged: bool,
+}
+
+/// Adds a message to the end of the queue. A retained message replaces a queued
+/// retained message of the same topic in place, as the broker would only keep
+/// the newer one anyway. This keeps a reconnect from filling the queue with
+/// the same discovery and availability messages again.
+pub fn enqueue<const N: usize>(
+    queue: &mut heapless::Deque<Outgoing, N>,
+    message: Outgoing,
+) -> Result<(), QueueFull> {
+    if message.retain {
+        if let Some(queued) = queue
+            .iter_mut()
+            .find(|queued| queued.retain && queued.topic == message.topic)
+        {
+            *queued = message;
+            return Ok(());
+        }
+    }
+    queue.push_back(message).map_err(|_| QueueFull)
+}
+
+/// Published by the broker if the connection is lost without a proper disconnect
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub retain: bool,
+}
+
+/// Content of CONNECT
+pub struct Connect<'a> {
+    pub client_id: &'a str,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    pub keep_alive_secs: u16,
+    pub will: Option<&'a Will<'a>>,
+}
+
+fn push_u16(packet: &mut Vec<u8>, value: u16) {
+    packet.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_string(packet: &mut Vec<u8>, string: &[u8]) {
+    push_u16(packet, string.len() as u16);
+    packet.extend_from_slice(string);
+}
+
+/// Puts the fixed header in front of the variable header and the payload
+fn with_fixed_header(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    packet.push(header);
+    let mut length = body.len();
+    loop {
+        let mut byte = (length & 0x7f) as u8;
+        length >>= 7;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+pub fn connect_packet(connect: &Connect) -> Vec<u8> {
+    let mut flags = CONNECT_CLEAN_SESSION;
+    if let Some(will) = connect.will {
+        flags |= CONNECT_WILL;
+        if will.retain {
+            flags |= CONNECT_WILL_RETAIN;
+        }
+    }
+    if connect.username.is_some() {
+        flags |= CONNECT_USERNAME;
+    }
+    if connect.password.is_some() {
+        flags |= CONNECT_PASSWORD;
+    }
+
+    let mut body = Vec::new();
+    push_string(&mut body, b"MQTT");
+    // Protocol level of 3.1.1
+    body.push(4);
+    body.push(flags);
+    push_u16(&mut body, connect.keep_alive_secs);
+    push_string(&mut body, connect.client_id.as_bytes());
+    if let Some(will) = connect.will {
+        push_string(&mut body, will.topic.as_bytes());
+        push_string(&mut body, will.payload);
+    }
+    if let Some(username) = connect.username {
+        push_string(&mut body, username.as_bytes());
+    }
+    if let Some(password) = connect.password {
+        push_string(&mut body, password.as_bytes());
+    }
+    with_fixed_header(CONNECT, &body)
+}
+
+/// QoS 1 with a packet id, QoS 0 without
+pub fn publish_packet(message: &Outgoing, packet_id: Option<u16>) -> Vec<u8> {
+    let mut header = PUBLISH;
+    if message.retain {
+        header |= PUBLISH_RETAIN;
+    }
+    let mut body = Vec::with_capacity(message.topic.len() + message.payload.len() + 4);
+    push_string(&mut body, message.topic.as_bytes());
+    if let Some(id) = packet_id {
+        header |= PUBLISH_QOS1;
+        push_u16(&mut body, id);
+    }
+    body.extend_from_slice(&message.payload);
+    with_fixed_header(header, &body)
+}
+
+pub fn puback_packet(packet_id: u16) -> Vec<u8> {
+    with_fixed_header(PUBACK, &packet_id.to_be_bytes())
+}
+
+pub fn subscribe_packet(packet_id: u16, topics: &[&str]) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_u16(&mut body, packet_id);
+    for topic in topics {
+        push_string(&mut body, topic.as_bytes());
+        // Requested QoS
+        body.push(1);
+    }
+    with_fixed_header(SUBSCRIBE, &body)
+}
+
+pub fn read_u16(data: &[u8], index: usize) -> Result<u16, Malformed> {
+    match data.get(index..index + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => Err(Malformed),
+    }
+}
+
+/// Looks for a complete packet at the start of the data. Packets ending beyond
+/// max_size are malformed, as they wouldn't fit into the receive buffer.
+/// Returns the first byte of the fixed header and the range of the remaining bytes.
+pub fn complete_packet(
+    data: &[u8],
+    max_size: usize,
+) -> Result<Option<(u8, Range<usize>)>, Malformed> {
+    let Some(&header) = data.first() else {
+        return Ok(None);
+    };
+    let mut length = 0;
+    // The remaining length has up to 4 bytes with 7 bits each
+    for (index, &byte) in data[1..].iter().take(4).enumerate() {
+        length |= ((byte & 0x7f) as usize) << (7 * index);
+        if byte & 0x80 == 0 {
+            let body = 2 + index..2 + index + length;
+            if body.end > max_size {
+                return Err(Malformed);
+            }
+            return Ok((body.end <= data.len()).then_some((header, body)));
+        }
+    }
+    match data.len() {
+        0..=4 => Ok(None),
+        _ => Err(Malformed),
+    }
+}
+
+/// Return code of CONNACK. 0 means accepted.
+pub fn connack_code(body: &[u8]) -> Result<u8, Malformed> {
+    match body {
+        [_, code] => Ok(*code),
+        _ => Err(Malformed),
+    }
+}
+
+/// True if the broker rejected any of the subscriptions
+pub fn suback_failed(body: &[u8]) -> Result<bool, Malformed> {
+    let codes = body.get(2..).ok_or(Malformed)?;
+    Ok(codes.contains(&SUBACK_FAILURE))
+}
+
+/// Parts of a received PUBLISH, as ranges of its body
+#[derive(Debug, PartialEq)]
+pub struct Publish {
+    pub topic: Range<usize>,
+    /// Requires an acknowledge if present
+    pub packet_id: Option<u16>,
+    pub payload: Range<usize>,
+}
+
+/// Splits the body of a PUBLISH with QoS 0 or 1
+pub fn parse_publish(header: u8, body: &[u8]) -> Result<Publish, Malformed> {
+    let topic_length = read_u16(body, 0)? as usize;
+    let topic = 2..2 + topic_length;
+    let (packet_id, payload_start) = match (header >> 1) & 3 {
+        0 => (None, topic.end),
+        1 => (Some(read_u16(body, topic.end)?), topic.end + 2),
+        // Never requested with the subscriptions
+        _ => return Err(Malformed),
+    };
+    if payload_start > body.len() {
+        return Err(Malformed);
+    }
+    core::str::from_utf8(&body[topic.clone()]).map_err(|_| Malformed)?;
+    Ok(Publish {
+        topic,
+        packet_id,
+        payload: payload_start..body.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec;
+
+    fn message(topic: &str, payload: &[u8], retain: bool) -> Outgoing {
+        Outgoing {
+            topic: String::from(topic),
+            payload: Vec::from(payload),
+            retain,
+            acknowledged: true,
+        }
+    }
+
+    #[test]
+    fn remaining_length_uses_up_to_four_bytes() {
+        for (length, encoded) in [
+            (0, vec![0x00]),
+            (127, vec![0x7f]),
+            (128, vec![0x80, 0x01]),
+            (16383, vec![0xff, 0x7f]),
+            (16384, vec![0x80, 0x80, 0x01]),
+            (2_097_152, vec![0x80, 0x80, 0x80, 0x01]),
+        ] {
+            let packet = with_fixed_header(PUBLISH, &vec![0; length]);
+            assert_eq!(packet[1..1 + encoded.len()], encoded, "{}", length);
+            let found = complete_packet(&packet, usize::MAX);
+            assert_eq!(
+                found,
+                Ok(Some((PUBLISH, packet.len() - length..packet.len())))
+            );
+        }
+    }
+
+    #[test]
+    fn connect_with_will_and_login() {
+        let will = Will {
+            topic: "c/a",
+            payload: b"offline",
+            retain: true,
+        };
+        let packet = connect_packet(&Connect {
+            client_id: "c",
+            username: Some("u"),
+            password: Some("p"),
+            keep_alive_secs: 30,
+            will: Some(&will),
+        });
+        let mut expected = vec![CONNECT, 33, 0, 4, b'M', b'Q', b'T', b'T', 4, 0xe6, 0, 30];
+        expected.extend_from_slice(&[0, 1, b'c', 0, 3, b'c', b'/', b'a', 0, 7]);
+        expected.extend_from_slice(b"offline");
+        expected.extend_from_slice(&[0, 1, b'u', 0, 1, b'p']);
+        assert_eq!(packet, expected);
+    }
+
+    #[test]
+    fn connect_without_options() {
+        let packet = connect_packet(&Connect {
+            client_id: "c",
+            username: None,
+            password: None,
+            keep_alive_secs: 60,
+            will: None,
+        });
+        let expected = [
+            CONNECT, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0x02, 0, 60, 0, 1, b'c',
+        ];
+        assert_eq!(packet, expected);
+    }
+
+    #[test]
+    fn publish_qos0_and_qos1() {
+        let packet = publish_packet(&message("a/b", b"on", false), None);
+        assert_eq!(packet, [PUBLISH, 7, 0, 3, b'a', b'/', b'b', b'o', b'n']);
+
+        let packet = publish_packet(&message("a/b", b"on", true), Some(0x1234));
+        let expected = [0x33, 9, 0, 3, b'a', b'/', b'b', 0x12, 0x34, b'o', b'n'];
+        assert_eq!(packet, expected);
+    }
+
+    #[test]
+    fn subscribe_requests_qos1() {
+        let packet = subscribe_packet(7, &["a/+"]);
+        assert_eq!(packet, [SUBSCRIBE, 8, 0, 7, 0, 3, b'a', b'/', b'+', 1]);
+        assert_eq!(puback_packet(7), [PUBACK, 2, 0, 7]);
+    }
+
+    #[test]
+    fn incomplete_packets_are_awaited() {
+        let packet = publish_packet(&message("a/b", b"on", false), None);
+        for end in 0..packet.len() {
+            assert_eq!(complete_packet(&packet[..end], 1024), Ok(None), "{}", end);
+        }
+        // Followed by the start of the next one
+        let mut data = packet.clone();
+        data.extend_from_slice(&[PINGRESP]);
+        assert_eq!(complete_packet(&data, 1024), Ok(Some((PUBLISH, 2..9))));
+    }
+
+    #[test]
+    fn oversized_and_malformed_packets_are_rejected() {
+        let packet = with_fixed_header(PUBLISH, &[0; 200]);
+        assert_eq!(complete_packet(&packet[..10], 100), Err(Malformed));
+        // The remaining length can't continue beyond 4 bytes
+        let data = [PUBLISH, 0x80, 0x80, 0x80, 0x80, 0x01];
+        assert_eq!(complete_packet(&data, usize::MAX), Err(Malformed));
+    }
+
+    #[test]
+    fn received_publish() {
+        let packet = publish_packet(&message("a/set/x", b"12", false), Some(3));
+        let (header, body) = complete_packet(&packet, 1024).unwrap().unwrap();
+        let body = &packet[body];
+        let publish = parse_publish(header, body).unwrap();
+        assert_eq!(&body[publish.topic], b"a/set/x");
+        assert_eq!(publish.packet_id, Some(3));
+        assert_eq!(&body[publish.payload], b"12");
+
+        let packet = publish_packet(&message("t", b"", false), None);
+        let publish = parse_publish(packet[0], &packet[2..]).unwrap();
+        assert_eq!(publish.packet_id, None);
+        assert!(publish.payload.is_empty());
+    }
+
+    #[test]
+    fn malformed_publish_is_rejected() {
+        // QoS 2
+        assert_eq!(parse_publish(0x34, &[0, 1, b't', 0, 1]), Err(Malformed));
+        // Topic longer than the packet
+        assert_eq!(parse_publish(PUBLISH, &[0, 5, b't']), Err(Malformed));
+        // Packet id missing
+        assert_eq!(parse_publish(0x32, &[0, 1, b't', 0]), Err(Malformed));
+        // Topic not UTF-8
+        assert_eq!(parse_publish(PUBLISH, &[0, 1, 0xff]), Err(Malformed));
+        assert_eq!(parse_publish(PUBLISH, &[0]), Err(Malformed));
+    }
+
+    #[test]
+    fn acknowledges() {
+        assert_eq!(connack_code(&[0, 0]), Ok(0));
+        assert_eq!(connack_code(&[0, 5]), Ok(5));
+        assert_eq!(connack_code(&[0]), Err(Malformed));
+        assert_eq!(suback_failed(&[0, 1, 1]), Ok(false));
+        assert_eq!(suback_failed(&[0, 1, 1, 0x80]), Ok(true));
+        assert_eq!(suback_failed(&[0]), Err(Malformed));
+    }
+
+    #[test]
+    fn retained_messages_replace_queued_ones_of_the_same_topic() {
+        let mut queue = heapless::Deque::<Outgoing, 4>::new();
+        enqueue(&mut queue, message("a", b"1", true)).unwrap();
+        enqueue(&mut queue, message("b", b"1", false)).unwrap();
+        enqueue(&mut queue, message("a", b"2", true)).unwrap();
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.front(), Some(&message("a", b"2", true)));
+
+        // Not retained messages are all delivered
+        enqueue(&mut queue, message("b", b"2", false)).unwrap();
+        enqueue(&mut queue, message("b", b"3", false)).unwrap();
+        assert_eq!(queue.len(), 4);
+        assert_eq!(
+            enqueue(&mut queue, message("c", b"1", true)),
+            Err(QueueFull)
+        );
+        // Still replaced if full
+        assert_eq!(enqueue(&mut queue, message("a", b"3", true)), Ok(()));
+    }
+
+    #[test]
+    fn repeated_reconnects_keep_the_queue_size() {
+        let mut queue = heapless::Deque::<Outgoing, 16>::new();
+        for _ in 0..10 {
+            enqueue(&mut queue, message("id/availability", b"online", true)).unwrap();
+            for entity in ["display", "brightness", "face", "beam_off", "beam_on"] {
+                enqueue(&mut queue, message(entity, b"{}", true)).unwrap();
+            }
+        }
+        assert_eq!(queue.len(), 6);
+    }
+
+    /// Exchanges packets with a broker on localhost, or the one given in MQTT_TEST_BROKER.
+    /// Run with mosquitto started: cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn local_broker() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+        use std::time::Duration;
+
+        let broker = std::env::var("MQTT_TEST_BROKER").unwrap_or("localhost:1883".into());
+        let mut stream = TcpStream::connect(broker).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut rx = vec![0; 1024];
+        let mut rx_len = 0;
+        let mut receive = |stream: &mut TcpStream| loop {
+            if let Some((header, body)) = complete_packet(&rx[..rx_len], rx.len()).unwrap() {
+                let packet = (header, rx[body.clone()].to_vec());
+                rx.copy_within(body.end..rx_len, 0);
+                rx_len -= body.end;
+                return packet;
+            }
+            let n = stream.read(&mut rx[rx_len..]).unwrap();
+            assert!(n > 0, "closed by the broker");
+            rx_len += n;
+        };
+
+        let id = std::format!("scopeclock-test-{}", std::process::id());
+        let will = Will {
+            topic: &std::format!("{}/availability", id),
+            payload: b"offline",
+            retain: false,
+        };
+        let connect = Connect {
+            client_id: &id,
+            username: None,
+            password: None,
+            keep_alive_secs: 30,
+            will: Some(&will),
+        };
+        stream.write_all(&connect_packet(&connect)).unwrap();
+        let (header, body) = receive(&mut stream);
+        assert_eq!((header, connack_code(&body)), (CONNACK, Ok(0)));
+
+        let filter = std::format!("{}/set/+", id);
+        stream.write_all(&subscribe_packet(1, &[&filter])).unwrap();
+        let (header, body) = receive(&mut stream);
+        assert_eq!((header, read_u16(&body, 0)), (SUBACK, Ok(1)));
+        assert_eq!(suback_failed(&body), Ok(false));
+
+        let command = Outgoing {
+            topic: std::format!("{}/set/brightness", id),
+            payload: Vec::from(&b"150"[..]),
+            retain: false,
+            acknowledged: true,
+        };
+        stream
+            .write_all(&publish_packet(&command, Some(2)))
+            .unwrap();
+        let mut acknowledged = false;
+        let mut delivered = false;
+        while !(acknowledged && delivered) {
+            let (header, body) = receive(&mut stream);
+            match header & 0xf0 {
+                PUBACK => {
+                    assert_eq!(read_u16(&body, 0), Ok(2));
+                    acknowledged = true;
+                }
+                PUBLISH => {
+                    let publish = parse_publish(header, &body).unwrap();
+                    assert_eq!(&body[publish.topic], command.topic.as_bytes());
+                    assert_eq!(&body[publish.payload], b"150");
+                    if let Some(id) = publish.packet_id {
+                        stream.write_all(&puback_packet(id)).unwrap();
+                    }
+                    delivered = true;
+                }
+                other => panic!("unexpected packet {:x}", other),
+            }
+        }
+
+        stream.write_all(&[PINGREQ, 0]).unwrap();
+        assert_eq!(receive(&mut stream), (PINGRESP, vec![]));
+    }
+}
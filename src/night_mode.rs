@@ -0,0 +1,459 @@
+use core::cell::RefCell;
+use core::fmt;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use chrono::{Datelike, Timelike};
+use critical_section::Mutex;
+
+/// How the clock is presented
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DisplayMode {
+    /// The complete clock face
+    Normal = 0,
+    /// The complete clock face with less samples per line
+    Dim = 1,
+    /// Only the hands with less samples and a slower refresh rate
+    Minimal = 2,
+    /// Beam is turned off and parked
+    Off = 3,
+}
+
+impl DisplayMode {
+    fn from_u8(value: u8) -> Option<DisplayMode> {
+        match value {
+            0 => Some(DisplayMode::Normal),
+            1 => Some(DisplayMode::Dim),
+            2 => Some(DisplayMode::Minimal),
+            3 => Some(DisplayMode::Off),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisplayMode::Normal => "normal",
+            DisplayMode::Dim => "dim",
+            DisplayMode::Minimal => "minimal",
+            DisplayMode::Off => "off",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<DisplayMode> {
+        [
+            DisplayMode::Normal,
+            DisplayMode::Dim,
+            DisplayMode::Minimal,
+            DisplayMode::Off,
+        ]
+        .into_iter()
+        .find(|mode| mode.name() == name)
+    }
+}
+
+/// Switches to a display mode during a time span on selected days.
+/// A rule might cross midnight. In that case it belongs to the day it starts.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rule {
+    /// Bit 0 is Monday, bit 6 is Sunday
+    pub weekdays: u8,
+    /// Minutes after midnight
+    pub start: u16,
+    /// Minutes after midnight. Exclusive
+    pub end: u16,
+    pub mode: DisplayMode,
+}
+
+const DAY_NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError;
+
+fn parse_day(name: &str) -> Result<u8, ParseError> {
+    DAY_NAMES
+        .iter()
+        .position(|d| *d == name)
+        .map(|d| d as u8)
+        .ok_or(ParseError)
+}
+
+fn parse_minutes(time: &str) -> Result<u16, ParseError> {
+    let (hours, minutes) = time.split_once(':').ok_or(ParseError)?;
+    let hours: u16 = hours.parse().map_err(|_| ParseError)?;
+    let minutes: u16 = minutes.parse().map_err(|_| ParseError)?;
+    if hours > 24 || minutes > 59 || hours * 60 + minutes > 24 * 60 {
+        return Err(ParseError);
+    }
+    Ok(hours * 60 + minutes)
+}
+
+/// Parses a single day like "sun" or a range like "mon-fri" into a mask
+fn parse_days(days: &str) -> Result<u8, ParseError> {
+    let Some((first, last)) = days.split_once('-') else {
+        return Ok(1 << parse_day(days)?);
+    };
+    let (first, last) = (parse_day(first)?, parse_day(last)?);
+    // Ranges may wrap around the end of the week
+    let mut mask = 0;
+    let mut day = first;
+    loop {
+        mask |= 1 << day;
+        if day == last {
+            break;
+        }
+        day = (day + 1) % 7;
+    }
+    Ok(mask)
+}
+
+impl Rule {
+    /// Parses a rule in the form of "mon-fri 23:00-06:30 off".
+    /// Days can also be "daily", a single day like "sun" or a list like "mon,wed,fri".
+    pub fn parse(text: &str) -> Result<Rule, ParseError> {
+        let mut words = text.split_whitespace();
+        let (days, times, mode) = match (words.next(), words.next(), words.next(), words.next()) {
+            (Some(days), Some(times), Some(mode), None) => (days, times, mode),
+            _ => return Err(ParseError),
+        };
+
+        let weekdays = if days == "daily" {
+            0x7f
+        } else {
+            let mut mask = 0;
+            for days in days.split(',') {
+                mask |= parse_days(days)?;
+            }
+            mask
+        };
+
+        let (start, end) = times.split_once('-').ok_or(ParseError)?;
+        Ok(Rule {
+            weekdays,
+            start: parse_minutes(start)?,
+            end: parse_minutes(end)?,
+            mode: DisplayMode::parse(mode).ok_or(ParseError)?,
+        })
+    }
+
+    fn has_day(&self, day: u32) -> bool {
+        self.weekdays & (1 << day) != 0
+    }
+
+    /// Checks if the rule applies. Day 0 is Monday.
+    pub fn is_active(&self, day: u32, minute: u16) -> bool {
+        if self.start <= self.end {
+            self.has_day(day) && (self.start..self.end).contains(&minute)
+        } else {
+            // Crosses midnight. The morning belongs to the day before
+            let yesterday = (day + 6) % 7;
+            (self.has_day(day) && minute >= self.start)
+                || (self.has_day(yesterday) && minute < self.end)
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weekdays == 0x7f {
+            write!(f, "daily")?;
+        } else {
+            let mut first = true;
+            for (day, name) in DAY_NAMES.iter().enumerate() {
+                if self.has_day(day as u32) {
+                    if !first {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", name)?;
+                    first = false;
+                }
+            }
+        }
+        write!(
+            f,
+            " {:02}:{:02}-{:02}:{:02} {}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60,
+            self.mode.name()
+        )
+    }
+}
+
+pub const MAX_RULES: usize = 8;
+
+static RULES: Mutex<RefCell<heapless::Vec<Rule, MAX_RULES>>> =
+    Mutex::new(RefCell::new(heapless::Vec::new()));
+
+/// Set from the network to ignore the rules. Stores a DisplayMode or AUTOMATIC.
+static OVERRIDE: AtomicU8 = AtomicU8::new(AUTOMATIC);
+const AUTOMATIC: u8 = 0xff;
+
+/// Replaces all rules by the ones separated by ';'
+pub fn set_rules(text: &str) -> Result<(), ParseError> {
+    let mut rules = heapless::Vec::new();
+    for rule in text.split(';').filter(|r| !r.trim().is_empty()) {
+        rules.push(Rule::parse(rule)?).map_err(|_| ParseError)?;
+    }
+    critical_section::with(|cs| *RULES.borrow_ref_mut(cs) = rules);
+    Ok(())
+}
+
+pub fn rules() -> heapless::Vec<Rule, MAX_RULES> {
+    critical_section::with(|cs| RULES.borrow_ref(cs).clone())
+}
+
+/// Forces a display mode. None returns to the rules.
+pub fn set_override(mode: Option<DisplayMode>) {
+    OVERRIDE.store(mode.map_or(AUTOMATIC, |m| m as u8), Ordering::Relaxed);
+}
+
+pub fn override_mode() -> Option<DisplayMode> {
+    DisplayMode::from_u8(OVERRIDE.load(Ordering::Relaxed))
+}
+
+/// Decides about the display mode for the given local time.
+/// The first matching rule wins. Without time only the override is considered.
+pub fn display_mode<T: Datelike + Timelike>(local_time: Option<&T>) -> DisplayMode {
+    if let Some(mode) = override_mode() {
+        return mode;
+    }
+
+    let Some(local_time) = local_time else {
+        return DisplayMode::Normal;
+    };
+    let day = local_time.weekday().num_days_from_monday();
+    let minute = (local_time.hour() * 60 + local_time.minute()) as u16;
+
+    critical_section::with(|cs| {
+        RULES
+            .borrow_ref(cs)
+            .iter()
+            .find(|rule| rule.is_active(day, minute))
+            .map_or(DisplayMode::Normal, |rule| rule.mode)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use std::string::ToString;
+
+    const MON: u32 = 0;
+    const TUE: u32 = 1;
+    const FRI: u32 = 4;
+    const SAT: u32 = 5;
+    const SUN: u32 = 6;
+
+    fn minute(time: &str) -> u16 {
+        parse_minutes(time).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        for (text, weekdays, start, end, mode) in [
+            (
+                "mon-fri 23:00-06:30 off",
+                0x1f,
+                23 * 60,
+                6 * 60 + 30,
+                DisplayMode::Off,
+            ),
+            ("daily 00:00-24:00 dim", 0x7f, 0, 24 * 60, DisplayMode::Dim),
+            (
+                "sun 01:05-08:00 minimal",
+                0x40,
+                65,
+                8 * 60,
+                DisplayMode::Minimal,
+            ),
+            (
+                "mon,wed,fri 12:00-13:00 off",
+                0x15,
+                12 * 60,
+                13 * 60,
+                DisplayMode::Off,
+            ),
+            // Wraps around the end of the week
+            (
+                "fri-mon 22:00-07:00 off",
+                0x71,
+                22 * 60,
+                7 * 60,
+                DisplayMode::Off,
+            ),
+            (
+                "sat-sat 10:00-11:00 normal",
+                0x20,
+                10 * 60,
+                11 * 60,
+                DisplayMode::Normal,
+            ),
+            (
+                "  tue  9:00-9:30 off ",
+                0x02,
+                9 * 60,
+                9 * 60 + 30,
+                DisplayMode::Off,
+            ),
+        ] {
+            let expected = Rule {
+                weekdays,
+                start,
+                end,
+                mode,
+            };
+            assert_eq!(Rule::parse(text), Ok(expected), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_invalid_rules() {
+        for text in [
+            "",
+            "mon-fri 23:00-06:30",
+            "mon-fri 23:00-06:30 off now",
+            "monday 23:00-06:30 off",
+            "mon-fri 23:00 off",
+            "mon-fri 23:00-06:60 off",
+            "mon-fri 24:01-06:00 off",
+            "mon-fri 25:00-06:00 off",
+            "mon-fri 2300-0630 off",
+            "mon-fri -1:00-06:00 off",
+            "mon-fri 23:00-06:30 bright",
+            "mon,,fri 23:00-06:30 off",
+        ] {
+            assert_eq!(Rule::parse(text), Err(ParseError), "{}", text);
+        }
+    }
+
+    #[test]
+    fn display_can_be_parsed_again() {
+        for (text, shown) in [
+            ("daily 00:00-24:00 dim", "daily 00:00-24:00 dim"),
+            (
+                "mon-fri 23:00-06:30 off",
+                "mon,tue,wed,thu,fri 23:00-06:30 off",
+            ),
+            (
+                "sun-tue 9:00-13:05 minimal",
+                "mon,tue,sun 09:00-13:05 minimal",
+            ),
+            ("tue,sun 00:00-00:01 normal", "tue,sun 00:00-00:01 normal"),
+        ] {
+            let rule = Rule::parse(text).unwrap();
+            assert_eq!(rule.to_string(), shown);
+            assert_eq!(Rule::parse(shown), Ok(rule));
+        }
+    }
+
+    #[test]
+    fn is_active() {
+        let weekday_nights = "mon-fri 23:00-06:30 off";
+        let week_end = "fri-sun 22:00-02:00 off";
+        let lunch = "daily 12:00-13:00 minimal";
+        for (text, day, time, active) in [
+            // Belongs to the night starting on Monday evening
+            (weekday_nights, MON, "22:59", false),
+            (weekday_nights, MON, "23:00", true),
+            (weekday_nights, MON, "23:59", true),
+            (weekday_nights, TUE, "00:00", true),
+            (weekday_nights, TUE, "06:29", true),
+            (weekday_nights, TUE, "06:30", false),
+            // Sunday night doesn't start one, Monday morning belongs to it
+            (weekday_nights, MON, "03:00", false),
+            (weekday_nights, SUN, "23:30", false),
+            // Friday night runs into Saturday
+            (weekday_nights, SAT, "06:00", true),
+            (weekday_nights, SAT, "23:30", false),
+            (weekday_nights, SUN, "03:00", false),
+            // The range wraps around the end of the week
+            (week_end, FRI, "22:00", true),
+            (week_end, SAT, "01:59", true),
+            (week_end, SUN, "23:00", true),
+            (week_end, MON, "01:00", true),
+            (week_end, MON, "02:00", false),
+            (week_end, MON, "22:00", false),
+            (week_end, FRI, "01:00", false),
+            // The end is exclusive
+            (lunch, TUE, "11:59", false),
+            (lunch, TUE, "12:00", true),
+            (lunch, SUN, "12:59", true),
+            (lunch, SUN, "13:00", false),
+            ("daily 00:00-24:00 off", SUN, "23:59", true),
+            ("daily 00:00-24:00 off", MON, "00:00", true),
+            ("daily 08:00-08:00 off", MON, "08:00", false),
+        ] {
+            let rule = Rule::parse(text).unwrap();
+            assert_eq!(
+                rule.is_active(day, minute(time)),
+                active,
+                "{} on day {} at {}",
+                text,
+                day,
+                time
+            );
+        }
+    }
+
+    /// The only test changing the rules and the override, as other tests run in parallel
+    #[test]
+    fn display_mode_prefers_the_override_and_the_first_rule() {
+        // 2024-01-01 is a Monday
+        let at = |day: u32, time: &str| {
+            let minutes = minute(time) as u32;
+            NaiveDate::from_ymd_opt(2024, 1, 1 + day)
+                .unwrap()
+                .and_hms_opt(minutes / 60, minutes % 60, 0)
+                .unwrap()
+        };
+        set_rules("mon-fri 23:00-06:30 off; daily 22:00-07:00 minimal;").unwrap();
+        assert_eq!(rules().len(), 2);
+
+        for (forced, day, time, expected) in [
+            (None, MON, "21:59", DisplayMode::Normal),
+            (None, MON, "22:00", DisplayMode::Minimal),
+            (None, MON, "23:00", DisplayMode::Off),
+            (None, TUE, "06:29", DisplayMode::Off),
+            (None, TUE, "06:30", DisplayMode::Minimal),
+            (None, TUE, "07:00", DisplayMode::Normal),
+            (None, SAT, "23:00", DisplayMode::Minimal),
+            (Some(DisplayMode::Normal), MON, "23:00", DisplayMode::Normal),
+            (Some(DisplayMode::Dim), TUE, "12:00", DisplayMode::Dim),
+        ] {
+            set_override(forced);
+            assert_eq!(override_mode(), forced);
+            assert_eq!(
+                display_mode(Some(&at(day, time))),
+                expected,
+                "{:?} on day {} at {}",
+                forced,
+                day,
+                time
+            );
+        }
+
+        // Without the time only the override counts
+        set_override(Some(DisplayMode::Off));
+        assert_eq!(
+            display_mode::<chrono::NaiveDateTime>(None),
+            DisplayMode::Off
+        );
+        set_override(None);
+        assert_eq!(
+            display_mode::<chrono::NaiveDateTime>(None),
+            DisplayMode::Normal
+        );
+
+        // An invalid rule keeps the previous ones
+        assert_eq!(
+            set_rules("daily 00:00-24:00 off; daily 25:00-26:00 off"),
+            Err(ParseError)
+        );
+        assert_eq!(rules().len(), 2);
+        let too_many = "daily 00:00-01:00 off;".repeat(MAX_RULES + 1);
+        assert_eq!(set_rules(&too_many), Err(ParseError));
+        set_rules("").unwrap();
+        assert!(rules().is_empty());
+    }
+}
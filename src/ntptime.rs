@@ -1,4 +1,5 @@
 use core::cell::Cell;
+use core::sync::atomic::{AtomicI32, AtomicU32, Ordering};
 
 use critical_section::Mutex;
 use embassy_net::udp::{self, UdpSocket};
@@ -36,6 +37,26 @@ impl NtpTimestampGenerator for StdTimestampGen {
 
 pub static PUBLIC_TIME: Mutex<Cell<Option<StdTimestampGen>>> = Mutex::new(Cell::new(None));
 
+/// Offset in µs between the local time and the NTP server measured last
+pub static LAST_OFFSET: AtomicI32 = AtomicI32::new(0);
+
+/// Uptime in seconds at the last successful request or NEVER_SYNCED
+pub static LAST_SYNC: AtomicU32 = AtomicU32::new(NEVER_SYNCED);
+pub const NEVER_SYNCED: u32 = u32::MAX;
+
+fn record_sync() {
+    LAST_SYNC.store(
+        embassy_time::Instant::now().as_secs() as u32,
+        Ordering::Relaxed,
+    );
+}
+
+fn record_offset(offset: i64) {
+    let offset = offset.clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+    LAST_OFFSET.store(offset, Ordering::Relaxed);
+    record_sync();
+}
+
 #[embassy_executor::task]
 pub async fn time_stuff(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
     let mut rx_buffer = [0; 1000];
@@ -97,6 +118,7 @@ pub async fn time_stuff(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>
                     system_now,
                     ((res.seconds_fraction as u64) * 1000000) >> 32
                 );
+                record_sync();
                 break;
             }
             Ok(Err(res)) => {
@@ -118,6 +140,7 @@ pub async fn time_stuff(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>
         match res {
             Ok(Ok(res)) => {
                 println!("NTP2 {:?}", res);
+                record_offset(res.offset);
 
                 if res.offset > 0 {
                     ntp_context.timestamp_gen.offset += Duration::from_micros(res.offset as u64);
@@ -149,6 +172,7 @@ pub async fn time_stuff(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>
         match res {
             Ok(Ok(res)) => {
                 println!("NTP3 {:?}", res);
+                record_offset(res.offset);
             }
             Ok(Err(res)) => {
                 println!("NTP3 Error {:?}", res);
@@ -1,138 +1,367 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
 use alloc::vec::Vec;
 
 #[path = "util.rs"]
 mod examples_util;
+use crate::calibration::{self, Calibration};
+use crate::display_backend::{ActiveBackend, DisplayBackend};
+use crate::frame_buffer::{FrameBuffer, SampleFormat};
+use crate::intensity;
+use crate::limits;
+use crate::screensaver::Appearance;
 use crate::{analog_clock_face::GLOBAL_SCALE, font::Drawing};
 
-use bresenham::Bresenham;
-
 use esp_backtrace as _;
 
 use libm::{ceilf, roundf};
 
 type Point = (isize, isize);
 
+pub use crate::frame_buffer::{Part, FULL_INTENSITY};
+
+/// Distance in logical units the beam travels per sample, in thousandths.
+/// Lines are sampled with this spacing independent of their direction,
+/// which gives every line the same brightness.
+/// Applies to the default sample rate of the backend and is scaled for others.
+pub static BEAM_SPEED: AtomicU32 = AtomicU32::new(1000);
+
+/// Samples per second used for the frames drawn next.
+/// The sampling of lines and dots is adjusted to keep the brightness.
+pub static SAMPLE_RATE: AtomicU32 = AtomicU32::new(ActiveBackend::SAMPLE_RATE);
+
+/// Brightness of everything drawn in percent, on top of the brightness of the parts.
+/// Lines are drawn slower and dots are exposed longer for a higher brightness.
+pub static BRIGHTNESS: AtomicU32 = AtomicU32::new(100);
+
+/// Maximum number of samples the beam rests on a vertex of a polygon.
+/// A full reversal gets all of them, a straight continuation gets none.
+/// Gives the deflection amplifiers time to reach the corner.
+pub static CORNER_DWELL: AtomicU32 = AtomicU32::new(0);
+
+/// Distance in logical units the beam is driven beyond a vertex of a polygon.
+/// Scaled by the sharpness of the corner. Pre-emphasis against rounded corners.
+pub static CORNER_OVERSHOOT: AtomicU32 = AtomicU32::new(0);
+
+/// Highest logical coordinate which can be presented by the DAC
+pub use crate::limits::MAX_COORDINATE;
+const _: () = assert!(MAX_COORDINATE as isize == 255 * GLOBAL_SCALE);
+
+/// Bits of the logical coordinates used for drawing
+const LOGICAL_BITS: u32 = if GLOBAL_SCALE == 2 { 9 } else { 8 };
+
+/// Bits of the coordinates stored in the frame buffer
+const NATIVE_BITS: u32 = ActiveBackend::RESOLUTION_BITS;
+
+/// Bytes of a sample in the frame buffer
+const SAMPLE_BYTES: usize = ActiveBackend::SAMPLE_BYTES;
+
+/// Samples stored for every point. Two dithered ones if the DAC lacks the lowest bit.
+const SAMPLES_PER_POINT: usize = match NATIVE_BITS < LOGICAL_BITS && GLOBAL_SCALE == 2 {
+    true => 2,
+    false => 1,
+};
+
+// The bounds of the settings assume the size of a point
+const _: () = assert!(SAMPLES_PER_POINT * SAMPLE_BYTES == limits::POINT_BYTES);
+
+/// Samples as stored by the active display backend
+pub struct ActiveFormat;
+
+impl SampleFormat for ActiveFormat {
+    const RESOLUTION_BITS: u32 = ActiveBackend::RESOLUTION_BITS;
+    const SAMPLE_BYTES: usize = ActiveBackend::SAMPLE_BYTES;
+    const BLANK_IN_SAMPLE: bool = ActiveBackend::BLANK_IN_SAMPLE;
+
+    fn encode(x: u16, y: u16, beam_on: bool, sample: &mut [u8]) {
+        ActiveBackend::encode(x, y, beam_on, sample);
+    }
+
+    fn decode(sample: &[u8]) -> (u16, u16) {
+        ActiveBackend::decode(sample)
+    }
+}
+
+/// Direction from a to b with a length of 1.
+/// None if both points are the same.
+fn unit_vector(a: Point, b: Point) -> Option<(f32, f32)> {
+    let dx = (b.0 - a.0) as f32;
+    let dy = (b.1 - a.1) as f32;
+    let length = libm::sqrtf(dx * dx + dy * dy);
+    if length > 0.0 {
+        Some((dx / length, dy / length))
+    } else {
+        None
+    }
+}
+
+/// Calls f for every line of the polygon.
+/// Also provides the point following the line to allow handling the corner.
 fn closed_polygon_to_lines<F>(points: &[Point], mut f: F)
 where
-    F: FnMut(Point, Point),
+    F: FnMut(Point, Point, Option<Point>),
 {
-    let first = points.iter();
-    let mut second = points.iter();
-    second.next();
-    for p1 in first {
-        let p2 = second.next().unwrap_or(points.first().unwrap());
-        f(*p1, *p2);
+    for (i, p1) in points.iter().enumerate() {
+        let p2 = points[(i + 1) % points.len()];
+        let p3 = points[(i + 2) % points.len()];
+        f(*p1, p2, Some(p3));
     }
 }
 
+/// Calls f for every line of the polyline.
+/// Also provides the point following the line, if there is one.
 fn open_polygon_to_lines<F>(points: &[Point], mut f: F)
 where
-    F: FnMut(Point, Point),
+    F: FnMut(Point, Point, Option<Point>),
 {
-    let first = points.iter();
-    let mut second = points.iter();
-    second.next();
-    for p1 in first {
-        if let Some(p2) = second.next() {
-            f(*p1, *p2);
+    for (i, pair) in points.windows(2).enumerate() {
+        f(pair[0], pair[1], points.get(i + 2).copied());
+    }
+}
+
+/// Moves and scales everything drawn on a picture.
+/// Scaling is done around the center of the clock face.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+    pub offset_x: f32,
+    pub offset_y: f32,
+    pub scale: f32,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        offset_x: 0.0,
+        offset_y: 0.0,
+        scale: 1.0,
+    };
+
+    fn apply(&self, x: u16, y: u16) -> (u16, u16) {
+        if *self == Self::IDENTITY {
+            return (x, y);
         }
+        let center = (0x82 * GLOBAL_SCALE) as f32;
+        let x = center + (x as f32 - center) * self.scale + self.offset_x;
+        let y = center + (y as f32 - center) * self.scale + self.offset_y;
+        (
+            roundf(x.clamp(0.0, MAX_COORDINATE)) as u16,
+            roundf(y.clamp(0.0, MAX_COORDINATE)) as u16,
+        )
     }
 }
 
 pub struct Picture<'a> {
-    pub tx_buffer: &'a mut [u8],
-    pub out_index: usize,
-    pub parts: Vec<(usize, usize)>,
+    /// Samples and parts drawn so far
+    pub frame: FrameBuffer<'a, ActiveFormat>,
+    /// Part transferred next by the display
     pub current_part: usize,
+    /// Applied to all points given to add_point
+    pub transform: Transform,
+    /// Adapts to the scope. Applied after the transform.
+    pub calibration: Calibration,
+    beam_speed: f32,
+    /// Brightness factor of the whole picture
+    brightness: f32,
+    /// Brightness factor of the parts drawn next
+    part_brightness: f32,
+    /// Intensity of the parts drawn next
+    intensity: u8,
+    /// Samples per second this picture is drawn for
+    pub sample_rate: u32,
+    corner_dwell: u32,
+    corner_overshoot: f32,
 }
 
 pub struct StaticPartMeta {
     pub out_index: usize,
-    pub parts: Vec<(usize, usize)>,
+    pub parts: Vec<Part>,
+    /// Used to draw the static part
+    pub appearance: Appearance,
+    /// The static part is only valid for this sample rate
+    pub sample_rate: u32,
+    /// and this calibration
+    pub calibration: Calibration,
+    /// The static part didn't fit into the frame buffer
+    pub truncated: bool,
 }
 
 impl<'a> Picture<'a> {
     pub fn new(tx_buffer: &'a mut [u8]) -> Picture<'a> {
         Self {
-            tx_buffer,
-            out_index: 0,
-            parts: Vec::new(),
+            frame: FrameBuffer::new(tx_buffer),
             current_part: 0,
+            transform: Transform::IDENTITY,
+            calibration: calibration::current(),
+            beam_speed: BEAM_SPEED
+                .load(Ordering::Relaxed)
+                .clamp(*limits::BEAM_SPEED.start(), *limits::BEAM_SPEED.end())
+                as f32
+                / 1000.0,
+            brightness: 1.0,
+            part_brightness: 1.0,
+            intensity: FULL_INTENSITY,
+            sample_rate: SAMPLE_RATE.load(Ordering::Relaxed),
+            corner_dwell: CORNER_DWELL.load(Ordering::Relaxed),
+            corner_overshoot: CORNER_OVERSHOOT.load(Ordering::Relaxed) as f32,
         }
     }
 
+    /// Divides the amount of samples used to draw lines and dots.
+    /// A factor larger than 1 makes everything drawn afterwards darker.
+    pub fn dim(&mut self, factor: f32) {
+        self.brightness /= factor;
+    }
+
+    /// Brightness in percent of the parts drawn afterwards. 100 is the normal brightness.
+    /// Parts with different brightness are still chained, as the brightness is
+    /// only a matter of the sample density.
+    pub fn set_brightness(&mut self, percent: u32) {
+        self.part_brightness = percent.max(1) as f32 / 100.0;
+    }
+
+    /// Intensity of the beam for the parts drawn afterwards, if an analog
+    /// Z output is available. Ignored otherwise to keep the parts chained.
+    pub fn set_intensity(&mut self, intensity: u8) {
+        if intensity::AVAILABLE {
+            self.intensity = intensity;
+        }
+    }
+
+    /// Samples required for the same exposure time as with the default sample rate
+    fn rate_factor(&self) -> f32 {
+        self.sample_rate as f32 / ActiveBackend::SAMPLE_RATE as f32
+    }
+
+    /// Distance the beam travels per sample on lines
+    fn line_step(&self) -> f32 {
+        self.beam_speed / (self.brightness * self.part_brightness * self.rate_factor())
+    }
+
+    /// Amount of samples for a dot with the given exposure at normal brightness
+    fn dot_samples(&self, exposure: usize) -> usize {
+        let factor = self.brightness * self.part_brightness * self.rate_factor();
+        let samples = roundf(exposure as f32 * factor) as usize;
+        samples.max(1)
+    }
+
     pub fn add_point(&mut self, x: u16, y: u16) {
-        if GLOBAL_SCALE == 2 {
-            self.add_raw_point((x >> 1) as u8, (y >> 1) as u8);
-
-            let second_x = if (x & 1) == 1 {
-                ((x + 1) >> 1) as u8
-            } else {
-                (x >> 1) as u8
-            };
-
-            let second_y = if (y & 1) == 1 {
-                ((y + 1) >> 1) as u8
-            } else {
-                (y >> 1) as u8
-            };
-
-            self.add_raw_point(second_x, second_y);
+        let (x, y) = self.transform.apply(x, y);
+        let (x, y) = self.calibration.apply(x, y);
+        if NATIVE_BITS >= LOGICAL_BITS {
+            // The DAC has enough resolution. No dithering required
+            let shift = NATIVE_BITS - LOGICAL_BITS;
+            self.frame.add_native_point(x << shift, y << shift);
+        } else if GLOBAL_SCALE == 2 {
+            self.frame.add_native_point(x >> 1, y >> 1);
+
+            let second_x = if (x & 1) == 1 { (x + 1) >> 1 } else { x >> 1 };
+            let second_y = if (y & 1) == 1 { (y + 1) >> 1 } else { y >> 1 };
+
+            self.frame.add_native_point(second_x, second_y);
         } else {
-            self.add_raw_point(x as u8, y as u8);
+            self.frame.add_native_point(x, y);
         }
     }
 
-    pub fn add_raw_point(&mut self, x: u8, y: u8) {
-        self.tx_buffer[self.out_index + 0] = 0;
-        self.tx_buffer[self.out_index + 1] = x as u8;
-        self.tx_buffer[self.out_index + 2] = 0;
-        self.tx_buffer[self.out_index + 3] = y as u8;
-        self.out_index += 4;
+    /// Registers all samples from start_index on as a part with the current intensity
+    fn finish_part(&mut self, start_index: usize) {
+        self.frame.finish_part(start_index, self.intensity);
     }
 
     pub fn add_dot(&mut self, x: u16, y: u16, exposure: usize) {
-        let start_index = self.out_index;
-        for _ in 0..exposure {
+        let start_index = self.frame.out_index;
+        for _ in 0..self.dot_samples(exposure) {
             self.add_point(x, y)
         }
-        self.parts.push((start_index, self.out_index));
+        self.finish_part(start_index);
     }
 
     pub fn add_dot2(&mut self, p: Point, exposure: usize) {
-        let start_index = self.out_index;
-        for _ in 0..exposure {
+        let start_index = self.frame.out_index;
+        for _ in 0..self.dot_samples(exposure) {
             self.add_point(p.0 as u16, p.1 as u16);
         }
-        self.parts.push((start_index, self.out_index));
+        self.finish_part(start_index);
+    }
+
+    /// Samples the line from a to b with a spacing of the beam speed, reduced by the brightness.
+    /// The end point itself is not part of the output to allow chaining of lines.
+    fn add_line_samples(&mut self, a: Point, b: Point) {
+        let dx = (b.0 - a.0) as f32;
+        let dy = (b.1 - a.1) as f32;
+        let length = libm::sqrtf(dx * dx + dy * dy);
+        let steps = ceilf(length / self.line_step()) as usize;
+
+        for i in 0..steps {
+            let t = i as f32 / steps as f32;
+            let x = roundf(a.0 as f32 + dx * t) as u16;
+            let y = roundf(a.1 as f32 + dy * t) as u16;
+            self.add_point(x, y);
+        }
     }
 
     pub fn add_line(&mut self, a: Point, b: Point) {
-        let start_index = self.out_index;
-        for (x, y) in Bresenham::new(a, b) {
-            self.add_point(x as u16, y as u16)
+        let start_index = self.frame.out_index;
+        self.add_line_samples(a, b);
+        self.add_point(b.0 as u16, b.1 as u16);
+        self.finish_part(start_index);
+    }
+
+    /// Compensates the lag of the deflection amplifiers at the vertex b
+    /// of a polygon going from a over b to c.
+    fn add_corner(&mut self, a: Point, b: Point, c: Point) {
+        let (Some(incoming), Some(outgoing)) = (unit_vector(a, b), unit_vector(b, c)) else {
+            return;
+        };
+
+        // 0 for a straight continuation up to 1 for a full reversal
+        let cosine = incoming.0 * outgoing.0 + incoming.1 * outgoing.1;
+        let sharpness = libm::acosf(cosine.clamp(-1.0, 1.0)) / core::f32::consts::PI;
+
+        if self.corner_overshoot > 0.0 {
+            // Move outwards from the corner. The difference of both directions is
+            // already longer for sharper corners.
+            let distance = self.corner_overshoot / 2.0;
+            let x = b.0 as f32 + (incoming.0 - outgoing.0) * distance;
+            let y = b.1 as f32 + (incoming.1 - outgoing.1) * distance;
+            self.add_point(
+                roundf(x.clamp(0.0, MAX_COORDINATE)) as u16,
+                roundf(y.clamp(0.0, MAX_COORDINATE)) as u16,
+            );
+        }
+
+        let dwell = roundf(self.corner_dwell as f32 * sharpness * self.rate_factor()) as u32;
+        for _ in 0..dwell {
+            self.add_point(b.0 as u16, b.1 as u16);
         }
-        self.parts.push((start_index, self.out_index));
     }
 
     pub fn add_closed_polygon(&mut self, points: &[Point]) {
-        let start_index = self.out_index;
-        closed_polygon_to_lines(points, |a, b| {
-            for (x, y) in Bresenham::new(a, b) {
-                self.add_point(x as u16, y as u16)
+        let start_index = self.frame.out_index;
+        closed_polygon_to_lines(points, |a, b, c| {
+            self.add_line_samples(a, b);
+            if let Some(c) = c {
+                self.add_corner(a, b, c);
             }
         });
-        self.parts.push((start_index, self.out_index));
+        // Close the loop by returning to the start
+        if let Some(first) = points.first() {
+            self.add_point(first.0 as u16, first.1 as u16);
+        }
+        self.finish_part(start_index);
     }
 
     pub fn add_open_polygon(&mut self, points: &[Point]) {
-        let start_index = self.out_index;
-        open_polygon_to_lines(points, |a, b| {
-            for (x, y) in Bresenham::new(a, b) {
-                self.add_point(x as u16, y as u16)
+        let start_index = self.frame.out_index;
+        open_polygon_to_lines(points, |a, b, c| {
+            self.add_line_samples(a, b);
+            if let Some(c) = c {
+                self.add_corner(a, b, c);
             }
         });
-        self.parts.push((start_index, self.out_index));
+        if let Some(last) = points.last() {
+            self.add_point(last.0 as u16, last.1 as u16);
+        }
+        self.finish_part(start_index);
     }
 
     pub fn add_circle(&mut self, center: Point, radius: f32, nodes: usize) {
@@ -1,60 +1,206 @@
 use core::cell::RefCell;
-use core::sync::atomic::AtomicU32;
+use core::ptr::write_volatile;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 
 use critical_section::Mutex;
 
 use hal::delay::Delay;
 use hal::gpio::{GpioPin, Output, PushPull};
-use hal::system::SoftwareInterrupt;
+use hal::system::{SoftwareInterrupt, SoftwareInterruptControl};
 
 #[path = "util.rs"]
 mod examples_util;
 
-use crate::analog_clock_face::{draw_dynamic_part, prepare_static_part};
-use crate::picture::{Picture, StaticPartMeta};
+use crate::analog_clock_face::{self, draw_dynamic_part, draw_minimal_face, prepare_static_part};
+use crate::calibration;
+#[cfg(feature = "circular-dma")]
+use crate::circular_dma::{chain_length, CircularDma, Descriptor};
+use crate::display_backend::{
+    ActiveBackend, DisplayBackend, I2S_INT_CLR_OFFSET, I2S_INT_ENA_OFFSET,
+};
+use crate::frame_exchange::{DisplayEnd, DrawingEnd, FrameExchange};
+use crate::intensity::{Intensity, IntensityOutput};
+use crate::limits::{self, FRAME_BUFFER_BYTES};
+use crate::message::{self, draw_message};
+use crate::night_mode::{self, DisplayMode};
+use crate::picture::{self, Picture, StaticPartMeta};
+use crate::screensaver::{self, Appearance};
+use crate::telemetry::{self, TELEMETRY};
+use crate::test_pattern;
+use crate::test_pattern_drawing::draw_test_pattern;
+use crate::tube_guard;
 
 use embassy_time::{Duration, Instant, Timer};
 use esp_backtrace as _;
 use esp_hal::gpio::OutputPin;
-use esp_hal::i2s::{DataFormat, I2s, I2sTx, I2sWriteDma, I2sWriteDmaTransfer, Standard};
 use esp_println::println;
 use examples_util::hal;
 use hal::clock::Clocks;
-use hal::dma::{DmaPriority, I2s0DmaChannel, I2s0DmaChannelCreator};
 use hal::interrupt::{CpuInterrupt, Priority};
-use hal::peripherals::{Interrupt, Peripherals, I2S0};
+use hal::peripherals::Interrupt;
 use hal::prelude::*;
-use hal::{dma_buffers, interrupt, Blocking};
+use hal::{dma_buffers, interrupt, Cpu};
 
 use static_cell::make_static;
 
-struct DmaData {
-    canvas: Option<&'static mut [u8]>,
-    next_display: Option<Picture<'static>>,
+/// Owns everything required to show frames on the scope.
+/// Shared with the interrupt handlers using DISPLAY, or owned by the drawing task with circular DMA.
+struct DisplayDriver {
+    backend: ActiveBackend,
+    frames: DisplayEnd<'static, Picture<'static>>,
     z_blank: GpioPin<Output<PushPull>, 32>,
+    intensity: IntensityOutput,
     delay: Delay,
+    software_interrupt: SoftwareInterruptControl,
+    /// Samples per second the backend is configured for
+    sample_rate: u32,
+    #[cfg(feature = "circular-dma")]
+    circular: CircularDma,
 }
-pub static WAIT_BEFORE_BEAM_OFF: AtomicU32 = AtomicU32::new(10);
+
+/// Busy loops of the NMI in high_level.S between the end of a part and the blanking.
+/// 50 are about one microsecond. Not used with circular DMA.
+#[no_mangle]
+pub static WAIT_BEFORE_BEAM_OFF: AtomicU32 = AtomicU32::new(50);
+
+/// Nanoseconds the beam rests blanked on the first sample of a part before it is enabled.
+/// Gives the deflection amplifiers time to settle after the jump. Not used with circular DMA.
 pub static WAIT_AFTER_BEAM_ON: AtomicU32 = AtomicU32::new(0);
 
-static DMA_DATA: Mutex<RefCell<Option<DmaData>>> = Mutex::new(RefCell::new(None));
+/// Requested frame rate in Hz. Frames are padded with blanked samples to reach it.
+/// 0 disables the padding and every frame is shown as fast as possible.
+pub static TARGET_FRAME_RATE: AtomicU32 = AtomicU32::new(15);
+
+/// Frame rate of the minimal face. Lower to let the beam rest longer.
+const MINIMAL_FRAME_RATE: u32 = *limits::FRAME_RATE.start();
+
+#[cfg(not(feature = "circular-dma"))]
+static DISPLAY: Mutex<RefCell<Option<DisplayDriver>>> = Mutex::new(RefCell::new(None));
+
+/// Raises SoftwareInterrupt2, which parks the APP CPU. Accessed directly, as the
+/// SoftwareInterruptControl belongs to the display.
+const DPORT_CPU_INTR_FROM_CPU_2_REG: *mut u32 = 0x3ff000e4 as *mut u32;
+
+/// Set by pause_display while the APP CPU shall stay parked
+static PARK_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Set by the APP CPU while it is parked
+static PARKED: AtomicBool = AtomicBool::new(false);
+
+/// Remembers the static part of the clock face and which frame buffers contain it
+pub struct StaticCache {
+    meta: StaticPartMeta,
+    /// Addresses of the frame buffers with a static part matching meta
+    buffers: heapless::Vec<usize, 2>,
+}
+
+impl StaticCache {
+    fn new(tx_buffer: &mut [u8], appearance: &Appearance) -> StaticCache {
+        let mut buffers = heapless::Vec::new();
+        buffers.push(tx_buffer.as_ptr() as usize).unwrap();
+        StaticCache {
+            meta: prepare_static_part(tx_buffer, appearance),
+            buffers,
+        }
+    }
+
+    /// Makes sure the frame buffer contains the static part with the given appearance
+    fn prepare(&mut self, tx_buffer: &mut [u8], appearance: &Appearance) {
+        let address = tx_buffer.as_ptr() as usize;
+        let sample_rate = picture::SAMPLE_RATE.load(Ordering::Relaxed);
+        if self.meta.appearance != *appearance
+            || self.meta.sample_rate != sample_rate
+            || self.meta.calibration != calibration::current()
+        {
+            // All buffers are outdated now
+            self.meta = prepare_static_part(tx_buffer, appearance);
+            self.buffers.clear();
+        } else if !self.buffers.contains(&address) {
+            // Drawing is deterministic. The meta data is the same
+            prepare_static_part(tx_buffer, appearance);
+        } else {
+            return;
+        }
+        self.buffers.push(address).ok();
+    }
+
+    /// To be called before the frame buffer is used for something else than the clock face
+    fn forget(&mut self, tx_buffer: &[u8]) {
+        let address = tx_buffer.as_ptr() as usize;
+        self.buffers.retain(|b| *b != address);
+    }
+}
+
+/// Everything the drawing task needs. Created on the APP CPU together with the display.
+pub struct Renderer {
+    static_cache: StaticCache,
+    frames: DrawingEnd<'static, Picture<'static>>,
+    /// There is no interrupt with circular DMA. The task follows the DMA instead.
+    #[cfg(feature = "circular-dma")]
+    display: DisplayDriver,
+}
+
+/// Name of what is shown right now. A test pattern, a message or the display mode of the clock.
+pub fn current_face() -> &'static str {
+    let mode = night_mode::display_mode(analog_clock_face::local_time().as_ref());
+    match test_pattern::selected() {
+        Some(pattern) => pattern.name(),
+        None if mode != DisplayMode::Off && message::current().is_some() => "message",
+        None => mode.name(),
+    }
+}
+
+fn draw_picture<'a>(tx_buffer: &'a mut [u8], static_cache: &mut StaticCache) -> Picture<'a> {
+    let appearance = screensaver::appearance(Instant::now().as_secs());
+
+    let mut picture = if let Some(pattern) = test_pattern::selected() {
+        static_cache.forget(tx_buffer);
+        draw_test_pattern(tx_buffer, pattern)
+    } else if appearance.blanked {
+        static_cache.forget(tx_buffer);
+        Picture::new(tx_buffer)
+    } else if let Some((text, shown)) = message::current() {
+        static_cache.forget(tx_buffer);
+        draw_message(tx_buffer, &text, shown, &appearance)
+    } else if appearance.minimal {
+        static_cache.forget(tx_buffer);
+        draw_minimal_face(tx_buffer, &appearance)
+    } else {
+        static_cache.prepare(tx_buffer, &appearance);
+        draw_dynamic_part(tx_buffer, &static_cache.meta)
+    };
 
-fn draw_picture<'a, 'b>(
-    tx_buffer: &'a mut [u8],
-    static_part_meta: &'b StaticPartMeta,
-) -> Picture<'a> {
-    draw_dynamic_part(tx_buffer, static_part_meta)
+    let rate = match appearance.minimal {
+        true => MINIMAL_FRAME_RATE,
+        false => TARGET_FRAME_RATE.load(Ordering::Relaxed),
+    };
+    let target_samples = match rate {
+        0 => 0,
+        rate => (picture.sample_rate / rate) as usize,
+    };
+    TELEMETRY
+        .frame_samples
+        .store(picture.frame.samples() as u32, Ordering::Relaxed);
+    if !picture.frame.finish_frame(target_samples) {
+        TELEMETRY.frame_overruns.fetch_add(1, Ordering::Relaxed);
+    }
+    if picture.frame.truncated {
+        TELEMETRY.frames_truncated.fetch_add(1, Ordering::Relaxed);
+    }
+    picture
 }
 
+/// Starts the display. To be called on the APP CPU, as interrupts are bound to the
+/// core enabling them.
 pub fn scopeclock_init(
-    i2s: I2S0,
+    backend_resources: <ActiveBackend as DisplayBackend>::Resources,
     clocks: &Clocks,
-    dma_channel: I2s0DmaChannelCreator,
     z_blank: GpioPin<Output<PushPull>, 32>,
+    intensity: IntensityOutput,
     delay: Delay,
-) -> StaticPartMeta {
-    let (tx_buffer1, tx_descriptors, _, rx_descriptors) = dma_buffers!(50000, 0);
-    let (tx_buffer2, _, _, _) = dma_buffers!(50000, 0);
+    software_interrupt: SoftwareInterruptControl,
+) -> Renderer {
+    let (tx_buffer1, tx_descriptors, _, rx_descriptors) = dma_buffers!(FRAME_BUFFER_BYTES, 0);
+    let (tx_buffer2, _, _, _) = dma_buffers!(FRAME_BUFFER_BYTES, 0);
 
     // For reasons I don't understand, dma_buffers!(...) doesn't provide descriptors with static lifetime.
     // We need to correct that here as the descriptors need to outlive I2s.
@@ -63,194 +209,282 @@ pub fn scopeclock_init(
 
     println!("{:?} descriptors", tx_descriptors.len());
 
-    let i2s = I2s::new(
-        i2s,
-        Standard::DAC,
-        DataFormat::Data16Channel16,
-        (44100 * 3).Hz(),
-        dma_channel.configure(
-            false,
-            tx_descriptors,
-            rx_descriptors,
-            DmaPriority::Priority0,
-        ),
-        clocks,
+    let backend = ActiveBackend::new(backend_resources, tx_descriptors, rx_descriptors, clocks);
+    #[cfg(feature = "circular-dma")]
+    let circular = CircularDma::new(
+        &backend,
+        [
+            make_static!([Descriptor::EMPTY; chain_length(FRAME_BUFFER_BYTES)]),
+            make_static!([Descriptor::EMPTY; chain_length(FRAME_BUFFER_BYTES)]),
+        ],
+        [&tx_buffer1[..], &tx_buffer2[..]],
     );
     let start = Instant::now();
 
-    let _static_part_meta = prepare_static_part(tx_buffer1);
-    let static_part_meta = prepare_static_part(tx_buffer2);
+    let appearance = screensaver::appearance(Instant::now().as_secs());
+    let mut static_cache = StaticCache::new(tx_buffer1, &appearance);
 
-    let drawing1 = draw_picture(tx_buffer1, &static_part_meta);
+    let drawing1 = draw_picture(tx_buffer1, &mut static_cache);
     println!("Drawing took {:?}ms", start.elapsed().as_millis());
-    let drawing2 = draw_picture(tx_buffer2, &static_part_meta);
+    let drawing2 = draw_picture(tx_buffer2, &mut static_cache);
 
-    println!("{} bytes", drawing1.out_index);
+    println!(
+        "{} bytes in {} parts",
+        drawing1.frame.out_index,
+        drawing1.frame.parts.len()
+    );
+    println!("{} steps of blank travel", drawing1.frame.blank_travel());
 
-    let dma_data = DmaData {
-        canvas: None,
-        next_display: Some(drawing1),
+    // There was no interrupt before the first frame. Avoid a bogus latency
+    telemetry::SCOPECLOCK_NMI_CYCLES.store(telemetry::cycle_count(), Ordering::Relaxed);
+
+    let exchange = make_static!(FrameExchange::<Picture<'static>>::new());
+    let (frames, display_end) = exchange.split(drawing2, drawing1);
+
+    let mut display = DisplayDriver {
+        backend,
+        frames: display_end,
         z_blank,
+        intensity,
         delay,
+        software_interrupt,
+        sample_rate: ActiveBackend::SAMPLE_RATE,
+        #[cfg(feature = "circular-dma")]
+        circular,
     };
 
-    critical_section::with(|cs| {
-        DMA_DATA.borrow_ref_mut(cs).replace(dma_data);
-    });
+    #[cfg(not(feature = "circular-dma"))]
+    {
+        update_frame(&mut display);
 
-    unsafe {
-        TX.replace(i2s.i2s_tx.build());
-        CURRENT_DISPLAY.replace(drawing2);
-        update_frame();
-    };
+        critical_section::with(|cs| {
+            DISPLAY.borrow_ref_mut(cs).replace(display);
+        });
+
+        interrupt::enable_direct(
+            ActiveBackend::INTERRUPT,
+            CpuInterrupt::Interrupt14NmiPriority7,
+        )
+        .unwrap();
+        interrupt::enable(Interrupt::FROM_CPU_INTR3, Priority::Priority3).unwrap();
+    }
 
-    interrupt::enable_direct(Interrupt::I2S0, CpuInterrupt::Interrupt14NmiPriority7).unwrap();
-    interrupt::enable(Interrupt::FROM_CPU_INTR3, Priority::Priority3).unwrap();
+    #[cfg(feature = "circular-dma")]
+    start_circular_dma(&mut display);
+
+    // Allows the PRO CPU to park this one, see pause_display
+    interrupt::enable(Interrupt::FROM_CPU_INTR2, Priority::Priority3).unwrap();
 
     //interrupt::enable(Interrupt::I2S0, Priority::Priority3).unwrap();
-    static_part_meta
+    Renderer {
+        static_cache,
+        frames,
+        #[cfg(feature = "circular-dma")]
+        display,
+    }
 }
 
 #[embassy_executor::task]
-pub async fn scopeclock_task(static_part_meta: StaticPartMeta) {
+pub async fn scopeclock_task(mut renderer: Renderer) {
+    let mut measurement_start = Instant::now();
+    let mut frames_at_start = TELEMETRY.frames_shown.load(Ordering::Relaxed);
+
     loop {
-        // Take the canvas if it exists
-        let canvas = critical_section::with(|cs| {
-            let mut dma_data = DMA_DATA.borrow_ref_mut(cs);
-            let dma_data = dma_data.as_mut().unwrap();
+        // Take the canvas if it exists and draw on it
+        if let Some(canvas) = renderer.frames.take_canvas() {
+            let start = Instant::now();
+            let drawing = draw_picture(canvas.frame.tx_buffer, &mut renderer.static_cache);
+            TELEMETRY
+                .draw_time_max
+                .fetch_max(start.elapsed().as_micros() as u32, Ordering::Relaxed);
+            //println!("{} bytes", drawing.frame.out_index);
+
+            #[cfg(feature = "circular-dma")]
+            renderer
+                .display
+                .circular
+                .queue(&drawing.frame.tx_buffer[0..drawing.frame.out_index]);
+            renderer.frames.submit(drawing);
+        }
 
-            dma_data.canvas.take()
-        });
+        #[cfg(feature = "circular-dma")]
+        follow_circular_dma(&mut renderer.display);
+
+        // Update the measured frame rate once per second
+        let elapsed = measurement_start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let frames = TELEMETRY.frames_shown.load(Ordering::Relaxed);
+            let frame_rate =
+                frames.wrapping_sub(frames_at_start) as u64 * 1_000_000 / elapsed.as_millis();
+            TELEMETRY
+                .frame_rate
+                .store(frame_rate as u32, Ordering::Relaxed);
+            measurement_start = Instant::now();
+            frames_at_start = frames;
+        }
 
-        // If there was a canvas we took, draw on it
-        if let Some(canvas) = canvas {
-            //let start = Instant::now();
-            let drawing = draw_picture(canvas, &static_part_meta);
-            //println!("Drawing took {:?}ms", start.elapsed().as_millis());
-            //println!("{} bytes", drawing.out_index);
+        Timer::after(Duration::from_millis(5)).await;
+    }
+}
 
-            critical_section::with(|cs| {
-                let mut dma_data = DMA_DATA.borrow_ref_mut(cs);
-                let dma_data = dma_data.as_mut().unwrap();
+/// Runs f while the APP CPU is parked in RAM and the display is stopped. Required to
+/// write the flash, as the cache is off meanwhile and the display partly runs from flash.
+/// The circular DMA doesn't depend on the CPU and keeps running.
+/// To be called on the PRO CPU outside of a critical section, as the APP CPU can't
+/// be parked while it waits for one.
+pub fn pause_display<R>(f: impl FnOnce() -> R) -> R {
+    // Nothing is transferred while parked
+    tube_guard::pause_watchdog();
+    PARK_REQUESTED.store(true, Ordering::Release);
+    unsafe { write_volatile(DPORT_CPU_INTR_FROM_CPU_2_REG, 1) };
+    while !PARKED.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
 
-                // Place the drawing into the next_display
-                dma_data.next_display.replace(drawing);
-            });
-        }
+    let result = f();
 
-        Timer::after(Duration::from_millis(5)).await;
+    PARK_REQUESTED.store(false, Ordering::Release);
+    while PARKED.load(Ordering::Acquire) {
+        core::hint::spin_loop();
     }
+    tube_guard::resume_watchdog();
+    result
 }
 
-// Must be shared mutable to allow usage with the DMA for now. write_dma of I2sTx demands it
-static mut CURRENT_DISPLAY: Option<Picture<'static>> = None;
+/// Circular mode: The whole frame is repeated by the DMA until the next one is queued.
+/// The beam stays on all the time, as there is no interrupt to blank it between the parts.
+#[cfg(feature = "circular-dma")]
+fn start_circular_dma(display: &mut DisplayDriver) {
+    let current = display.frames.current();
+    display
+        .circular
+        .start(&current.frame.tx_buffer[0..current.frame.out_index]);
+
+    // The other frame waits in the frame exchange and is queued right away
+    let next = display.frames.next().unwrap();
+    display
+        .circular
+        .queue(&next.frame.tx_buffer[0..next.frame.out_index]);
+
+    display.intensity.set_level(picture::FULL_INTENSITY);
+    display.z_blank.set_output_high(false);
+}
 
-// Write_dma call requires I2sTx being borrowed for static
-static mut TX: Option<I2sTx<'static, I2S0, I2s0DmaChannel, Blocking>> = None;
+/// Circular mode: Polled by the drawing task instead of the interrupts.
+/// Frees the shown frame for drawing once the DMA has moved on to the next one.
+#[cfg(feature = "circular-dma")]
+fn follow_circular_dma(display: &mut DisplayDriver) {
+    // Counted as parts for the tube guard, which expects progress of the DMA
+    if display.circular.made_progress() {
+        TELEMETRY.parts_transferred.fetch_add(1, Ordering::Relaxed);
+    }
+    if display.circular.switched_to_queued() {
+        display.frames.advance();
+        // A few samples of the new frame were already output with the old rate
+        apply_sample_rate(display);
+        TELEMETRY.frames_shown.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
+/// Configures the backend for the sample rate the current frame was drawn for
 #[ram]
-unsafe fn select_next_picture(dma_data: &mut DmaData) {
-    if let Some(next) = dma_data.next_display.take() {
-        // So we have a next picture to display?
-        // Move canvas <- current to have another free canvas to draw on
-        // current <- next
-        // next is left empty in that case
-        let current = CURRENT_DISPLAY.take().unwrap();
-        dma_data.canvas = Some(current.tx_buffer);
-        CURRENT_DISPLAY.replace(next);
-    } else {
-        //println!("Missed frame");
+fn apply_sample_rate(display: &mut DisplayDriver) {
+    let sample_rate = display.frames.current().sample_rate;
+    if sample_rate != display.sample_rate {
+        match display.backend.set_sample_rate(sample_rate) {
+            Ok(()) => display.sample_rate = sample_rate,
+            // Let the next frames be drawn for the rate which is still used
+            Err(_) => picture::SAMPLE_RATE.store(display.sample_rate, Ordering::Relaxed),
+        }
     }
 }
 
+/// Switches to the next frame after the current one was shown completely
+#[cfg(not(feature = "circular-dma"))]
 #[ram]
-// Function is not reentrant!
-unsafe fn update_frame() {
-    static mut TRANSFER: Option<
-        I2sWriteDmaTransfer<'static, 'static, I2S0, I2s0DmaChannel, Blocking>,
-    > = None;
-
-    //let transfer_line_for_line = (embassy_time::Instant::now().as_secs() % 10) >= 5;
-    let transfer_line_for_line = true;
-    let stolen_peripheral = Peripherals::steal(); // TODO find a way around this
-    let stolen_i2s0 = stolen_peripheral.I2S0;
+fn select_next_picture(display: &mut DisplayDriver) {
+    if !display.frames.advance() {
+        // Nothing new was drawn in time. Show the same picture again
+        TELEMETRY.frames_missed.fetch_add(1, Ordering::Relaxed);
+    }
+    // The DMA is idle between two frames
+    apply_sample_rate(display);
+    TELEMETRY.frames_shown.fetch_add(1, Ordering::Relaxed);
+}
 
-    critical_section::with(|cs| {
-        let mut dma_data = DMA_DATA.borrow_ref_mut(cs);
-        let dma_data = dma_data.as_mut().unwrap();
+/// Provides the next segment of the frame to transfer and the intensity of the beam.
+/// The last segment of every frame is the blanked idle time without intensity.
+/// None if the frame is complete.
+#[cfg(not(feature = "circular-dma"))]
+fn next_segment(picture: &mut Picture) -> Option<((usize, usize), Option<u8>)> {
+    let segment = match picture.frame.parts.get(picture.current_part) {
+        Some(&(start, end, intensity)) => ((start, end), Some(intensity)),
+        None if picture.current_part == picture.frame.parts.len() => (picture.frame.idle, None),
+        None => return None,
+    };
+    picture.current_part += 1;
+    Some(segment)
+}
 
-        if let Some(mut t) = TRANSFER.take() {
-            t.clear_int();
-            t.wait().unwrap();
+/// Starts the transfer of the next part.
+/// Called after the DMA has finished the previous part and the beam was blanked.
+#[cfg(not(feature = "circular-dma"))]
+#[ram]
+fn update_frame(display: &mut DisplayDriver) {
+    // look for next part to show
+    let (indizes, beam) = match next_segment(display.frames.current_mut()) {
+        Some(segment) => segment,
+        None => {
+            select_next_picture(display);
+
+            // The frame might have been swapped. Let's grab it again
+            let current_display = display.frames.current_mut();
+            current_display.current_part = 0;
+            next_segment(current_display).unwrap()
         }
+    };
+    // As early as possible, to give the analog Z output time to settle while blanked
+    if let Some(intensity) = beam {
+        display.intensity.set_level(intensity);
+    }
+    let tx_slice = &display.frames.current().frame.tx_buffer[indizes.0..indizes.1];
+
+    // Make a small transfer to establish the first required sample for the next line
+    // This gives us the possibility to wait some time before disabling blank
+    let tx_startslice = &tx_slice[0..ActiveBackend::SAMPLE_BYTES];
+    display
+        .backend
+        .write_blocking(tx_startslice, &display.delay);
+    let settle_time = WAIT_AFTER_BEAM_ON.load(Ordering::Relaxed);
+    if settle_time > 0 {
+        display.delay.delay_nanos(settle_time);
+    }
 
-        // look for next picture to show
-        let tx_slice = if transfer_line_for_line {
-            let current_display = CURRENT_DISPLAY.as_mut().unwrap();
-
-            let indizes =
-                if let Some(indizes) = current_display.parts.get(current_display.current_part) {
-                    //current_display.parts.remove(0)
-                    current_display.current_part += 1;
-                    *indizes
-                } else {
-                    select_next_picture(dma_data);
-
-                    // CURRENT_DISPLAY was swapped. Let's grab it again
-                    let current_display = CURRENT_DISPLAY.as_mut().unwrap();
-                    current_display.current_part = 1;
-                    current_display.parts[0]
-                };
-            let to_draw = CURRENT_DISPLAY.as_mut().unwrap();
-            let tx_slice = &to_draw.tx_buffer[indizes.0..indizes.1];
-            tx_slice
-        } else {
-            select_next_picture(dma_data);
-
-            let to_draw = CURRENT_DISPLAY.as_ref().unwrap();
-            let tx_slice = &to_draw.tx_buffer[0..to_draw.out_index];
-            tx_slice
-        };
-
-        // Make a small transfer to establish the first required sample for the next line
-        // This gives us the possibility to wait some time before disabling blank
-        let tx: &mut I2sTx<'_, I2S0, I2s0DmaChannel, Blocking> = TX.as_mut().unwrap();
-        let tx_startslice = &tx_slice[0..4];
-        let transfer = tx.write_dma(&tx_startslice).unwrap();
-        // delay to give the DMA some time to activate
-        dma_data.delay.delay_nanos(10);
-        transfer.wait().unwrap();
-
-        //dma_data.delay.delay_micros(10);
-
-        // before leaving function, put tx_slice into something with static lifetime
-        static mut CURRENT_TX_SLICE: Option<&[u8]> = None;
-        let tx_slice = {
-            CURRENT_TX_SLICE.replace(tx_slice);
-            CURRENT_TX_SLICE.as_ref().unwrap()
-        };
-
-        let transfer = tx.write_dma(tx_slice).unwrap();
-        stolen_i2s0
-            .int_clr()
-            .write(|f| f.tx_rempty().clear_bit_by_one());
-
-        TRANSFER = Some(transfer);
-
-        // enable the beam after a short pause
-        dma_data.z_blank.set_output_high(false);
-        // delay to give the DMA some time to activate
-        dma_data.delay.delay_nanos(10);
-
-        stolen_i2s0
-            .int_clr()
-            .write(|f| f.tx_rempty().clear_bit_by_one());
-        stolen_i2s0.int_ena().write(|f| f.tx_rempty().set_bit());
-    });
+    // SAFETY: The frame buffers are static. The frame stays in the current slot of the
+    // frame exchange until the last part was transferred, so it can't be drawn on while
+    // being read. The next part is only started by the tx_rempty interrupt of this one.
+    unsafe { display.backend.start_write(tx_slice) };
+    display.backend.clear_tx_rempty();
+    TELEMETRY.parts_transferred.fetch_add(1, Ordering::Relaxed);
+
+    // enable the beam after a short pause
+    if beam.is_some() {
+        display.z_blank.set_output_high(false);
+        TELEMETRY.record_beam_on();
+    }
+    // delay to give the DMA some time to activate
+    display.delay.delay_nanos(10);
+
+    display.backend.clear_tx_rempty();
+    display.backend.enable_tx_rempty();
 }
 
 use core::arch::asm;
-core::arch::global_asm!(include_str!("high_level.S"));
+core::arch::global_asm!(
+    include_str!("high_level.S"),
+    i2s_int_clr = const ActiveBackend::I2S_BASE + I2S_INT_CLR_OFFSET,
+    i2s_int_ena = const ActiveBackend::I2S_BASE + I2S_INT_ENA_OFFSET,
+);
 
 #[naked]
 #[no_mangle]
@@ -259,14 +493,52 @@ unsafe extern "C" fn __naked_level_7_interrupt() {
     asm!("HANDLE_INTERRUPT_LEVEL2 7", options(noreturn));
 }
 
+#[cfg(not(feature = "circular-dma"))]
 #[ram]
 #[interrupt]
 fn FROM_CPU_INTR3() {
-    // Clear the software interrupt
-    let stolen_peripheral = unsafe { Peripherals::steal() };
-    let system = stolen_peripheral.SYSTEM.split();
-    let mut sw_int = system.software_interrupt_control;
-    sw_int.reset(SoftwareInterrupt::SoftwareInterrupt3);
+    critical_section::with(|cs| {
+        let mut display = DISPLAY.borrow_ref_mut(cs);
+        let display = display.as_mut().unwrap();
 
-    unsafe { update_frame() };
+        // Clear the software interrupt
+        display
+            .software_interrupt
+            .reset(SoftwareInterrupt::SoftwareInterrupt3);
+
+        update_frame(display);
+    });
+}
+
+/// Keeps the APP CPU in RAM while pause_display runs on the PRO CPU
+#[ram]
+#[interrupt]
+fn FROM_CPU_INTR2() {
+    unsafe { write_volatile(DPORT_CPU_INTR_FROM_CPU_2_REG, 0) };
+
+    #[cfg(not(feature = "circular-dma"))]
+    {
+        interrupt::disable(Cpu::AppCpu, ActiveBackend::INTERRUPT);
+        // The DMA repeats the last sample until the interrupt is back
+        critical_section::with(|cs| {
+            if let Some(display) = DISPLAY.borrow_ref_mut(cs).as_mut() {
+                display.z_blank.set_output_high(true);
+            }
+        });
+    }
+
+    // Outside of the critical section, as the PRO CPU might need it to write the flash
+    PARKED.store(true, Ordering::Release);
+    while PARK_REQUESTED.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+    PARKED.store(false, Ordering::Release);
+
+    // The pending interrupt of the finished part continues with the next one
+    #[cfg(not(feature = "circular-dma"))]
+    interrupt::enable_direct(
+        ActiveBackend::INTERRUPT,
+        CpuInterrupt::Interrupt14NmiPriority7,
+    )
+    .unwrap();
 }
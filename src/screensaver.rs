@@ -0,0 +1,81 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::analog_clock_face::{self, GLOBAL_SCALE};
+use crate::night_mode::{self, DisplayMode};
+use crate::picture::{self, Transform};
+
+/// Moves the whole picture slowly in a circle to avoid burning it into the tube
+pub static SCREENSAVER_ENABLED: AtomicBool = AtomicBool::new(true);
+/// Radius of the orbit in DAC steps
+pub static ORBIT_RADIUS: AtomicU32 = AtomicU32::new(3);
+/// Time in seconds for a full orbit
+pub static ORBIT_PERIOD: AtomicU32 = AtomicU32::new(600);
+/// The picture is shrunk by this amount of percent every other half hour
+pub static SHRINK_PERCENT: AtomicU32 = AtomicU32::new(2);
+
+/// Time in seconds after which the draw order is inverted
+const INVERT_PERIOD: u64 = 15 * 60;
+
+/// Lines are drawn with less samples when dimmed
+const NIGHT_DIM_FACTOR: f32 = 2.0;
+
+/// Everything which decides how the clock face is drawn
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Appearance {
+    pub transform: Transform,
+    /// Parts are drawn in opposite order and direction
+    pub reversed: bool,
+    /// Factor to reduce the amount of samples of lines and dots
+    pub dim_factor: f32,
+    /// Nothing is drawn at all
+    pub blanked: bool,
+    /// Only the hands are drawn
+    pub minimal: bool,
+}
+
+impl Appearance {
+    pub const DEFAULT: Appearance = Appearance {
+        transform: Transform::IDENTITY,
+        reversed: false,
+        dim_factor: 1.0,
+        blanked: false,
+        minimal: false,
+    };
+}
+
+/// Decides about the appearance, based on the time since boot and the local time.
+/// All changes are stepwise to avoid redrawing the static part of the clock face too often.
+pub fn appearance(uptime_secs: u64) -> Appearance {
+    let mut appearance = Appearance::DEFAULT;
+    appearance.dim_factor = 100.0 / picture::BRIGHTNESS.load(Ordering::Relaxed).max(1) as f32;
+
+    let local_time = analog_clock_face::local_time();
+    match night_mode::display_mode(local_time.as_ref()) {
+        DisplayMode::Normal => {}
+        DisplayMode::Dim => appearance.dim_factor *= NIGHT_DIM_FACTOR,
+        DisplayMode::Minimal => {
+            appearance.dim_factor *= NIGHT_DIM_FACTOR;
+            appearance.minimal = true;
+        }
+        DisplayMode::Off => appearance.blanked = true,
+    }
+
+    if !SCREENSAVER_ENABLED.load(Ordering::Relaxed) {
+        return appearance;
+    }
+
+    // Orbit with whole DAC steps
+    let period = ORBIT_PERIOD.load(Ordering::Relaxed).max(1) as u64;
+    let phi = (uptime_secs % period) as f32 / period as f32 * core::f32::consts::PI * 2.0;
+    let radius = (ORBIT_RADIUS.load(Ordering::Relaxed) as isize * GLOBAL_SCALE) as f32;
+    appearance.transform.offset_x = libm::roundf(libm::sinf(phi) * radius);
+    appearance.transform.offset_y = libm::roundf(libm::cosf(phi) * radius);
+
+    let phase = uptime_secs / INVERT_PERIOD;
+    appearance.reversed = phase % 2 == 1;
+    if (phase / 2) % 2 == 1 {
+        appearance.transform.scale = 1.0 - SHRINK_PERCENT.load(Ordering::Relaxed) as f32 / 100.0;
+    }
+
+    appearance
+}
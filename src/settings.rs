@@ -0,0 +1,54 @@
+//! Applies the commands received over MQTT and HTTP to the running clock.
+
+use core::sync::atomic::Ordering;
+
+use esp_println::println;
+
+use crate::command::{Command, Error};
+use crate::{
+    calibration, calibration_storage, limits, message, night_mode, picture, scopeclock,
+    screensaver, test_pattern,
+};
+
+/// Applies a command which was already checked by Command::parse
+fn execute(command: Command) -> Result<(), Error> {
+    match command {
+        Command::BeamOff(p) => scopeclock::WAIT_BEFORE_BEAM_OFF.store(p, Ordering::Relaxed),
+        Command::BeamOn(p) => scopeclock::WAIT_AFTER_BEAM_ON.store(p, Ordering::Relaxed),
+        Command::BeamSpeed(p) => picture::BEAM_SPEED.store(p, Ordering::Relaxed),
+        Command::Brightness(p) => picture::BRIGHTNESS.store(p, Ordering::Relaxed),
+        Command::SampleRate(p) => picture::SAMPLE_RATE.store(p, Ordering::Relaxed),
+        Command::CornerDwell(p) => picture::CORNER_DWELL.store(p, Ordering::Relaxed),
+        Command::CornerOvershoot(p) => picture::CORNER_OVERSHOOT.store(p, Ordering::Relaxed),
+        Command::FrameRate(p) => scopeclock::TARGET_FRAME_RATE.store(p, Ordering::Relaxed),
+        Command::Screensaver(p) => screensaver::SCREENSAVER_ENABLED.store(p, Ordering::Relaxed),
+        Command::OrbitRadius(p) => screensaver::ORBIT_RADIUS.store(p, Ordering::Relaxed),
+        Command::NightRules(rules) => night_mode::set_rules(rules).map_err(|_| Error::Invalid)?,
+        Command::DisplayMode(mode) => night_mode::set_override(mode),
+        Command::Calibration(settings) => {
+            calibration::update(&settings);
+        }
+        Command::TestPattern(pattern) => test_pattern::select(pattern),
+        Command::CalibrationSave => calibration_storage::save().map_err(|e| {
+            println!("Saving calibration failed: {:?}", e);
+            Error::Failed
+        })?,
+        Command::Message(text) => {
+            message::show(text).map_err(|_| Error::TooLong(limits::MESSAGE_LENGTH))?
+        }
+        Command::MessageTimeout(p) => message::TIMEOUT.store(p, Ordering::Relaxed),
+    }
+    Ok(())
+}
+
+/// Parses and executes a command. Logs the outcome on the console.
+pub fn run(name: &str, payload: &[u8]) -> Result<(), Error> {
+    let result = Command::parse(name, payload).and_then(|command| {
+        println!("Command {:?}", command);
+        execute(command)
+    });
+    if let Err(e) = &result {
+        println!("Command {} {:?} rejected: {}", name, payload, e);
+    }
+    result
+}
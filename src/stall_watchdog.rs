@@ -0,0 +1,173 @@
+/// Shortest time after which the display is considered stalled
+const MIN_DEADLINE_MS: u64 = 200;
+
+/// Decides if the display is stalled, based on the progress of the parts transfer counter.
+/// Doesn't access any hardware. All times are in milliseconds.
+pub struct StallWatchdog {
+    deadline: u64,
+    last_count: u32,
+    last_progress: u64,
+    paused: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Healthy,
+    Stalled,
+}
+
+impl StallWatchdog {
+    pub const fn new() -> Self {
+        Self {
+            deadline: MIN_DEADLINE_MS,
+            last_count: 0,
+            last_progress: 0,
+            paused: false,
+        }
+    }
+
+    /// Adapts the deadline to the longest time a frame can take.
+    /// The deadline must be longer than the longest part and the idle time at the end
+    /// of a frame. Twice the frame period also covers a change of the sample rate.
+    pub fn set_frame_period(&mut self, period: u64) {
+        self.deadline = (2 * period).max(MIN_DEADLINE_MS);
+    }
+
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+
+    /// No progress is expected until resume() is called
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// The deadline restarts at the given time
+    pub fn resume(&mut self, now: u64) {
+        self.paused = false;
+        self.last_progress = now;
+    }
+
+    /// To be called periodically with the current time and the number of parts transferred so far
+    pub fn check(&mut self, now: u64, count: u32) -> Verdict {
+        if count != self.last_count || self.paused {
+            self.last_count = count;
+            self.last_progress = now;
+        }
+
+        if now.saturating_sub(self.last_progress) >= self.deadline {
+            Verdict::Stalled
+        } else {
+            Verdict::Healthy
+        }
+    }
+}
+
+impl Default for StallWatchdog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks every 10 ms like the tube guard, with the count advanced by progress()
+    fn run(
+        watchdog: &mut StallWatchdog,
+        time: &mut u64,
+        count: &mut u32,
+        duration: u64,
+        progress: impl Fn(u64) -> bool,
+    ) -> Verdict {
+        let mut verdict = Verdict::Healthy;
+        for _ in 0..duration / 10 {
+            *time += 10;
+            if progress(*time) {
+                // Like fetch_add of the telemetry counter
+                *count = count.wrapping_add(1);
+            }
+            verdict = watchdog.check(*time, *count);
+            if verdict == Verdict::Stalled {
+                break;
+            }
+        }
+        verdict
+    }
+
+    #[test]
+    fn progress_keeps_it_healthy() {
+        let mut watchdog = StallWatchdog::new();
+        let (mut time, mut count) = (0, 0);
+        let verdict = run(&mut watchdog, &mut time, &mut count, 10_000, |_| true);
+        assert_eq!(verdict, Verdict::Healthy);
+    }
+
+    #[test]
+    fn stall_is_detected_after_the_deadline() {
+        let mut watchdog = StallWatchdog::new();
+        let (mut time, mut count) = (0, 0);
+        run(&mut watchdog, &mut time, &mut count, 1000, |_| true);
+        let stalled_since = time;
+        let verdict = run(&mut watchdog, &mut time, &mut count, 1000, |_| false);
+        assert_eq!(verdict, Verdict::Stalled);
+        assert_eq!(time - stalled_since, MIN_DEADLINE_MS);
+    }
+
+    #[test]
+    fn count_wrapping_around_is_progress() {
+        let mut watchdog = StallWatchdog::new();
+        let (mut time, mut count) = (0, u32::MAX - 5);
+        let verdict = run(&mut watchdog, &mut time, &mut count, 1000, |_| true);
+        assert_eq!(verdict, Verdict::Healthy);
+        assert!(count < 100);
+    }
+
+    #[test]
+    fn slow_frames_extend_the_deadline() {
+        let mut watchdog = StallWatchdog::new();
+        // A frame every 400 ms, e.g. with a low sample rate
+        watchdog.set_frame_period(400);
+        assert_eq!(watchdog.deadline(), 800);
+        let (mut time, mut count) = (0, 0);
+        let verdict = run(&mut watchdog, &mut time, &mut count, 10_000, |t| {
+            t % 400 == 0
+        });
+        assert_eq!(verdict, Verdict::Healthy);
+
+        let verdict = run(&mut watchdog, &mut time, &mut count, 1000, |_| false);
+        assert_eq!(verdict, Verdict::Stalled);
+    }
+
+    #[test]
+    fn fast_frames_keep_the_minimal_deadline() {
+        let mut watchdog = StallWatchdog::new();
+        watchdog.set_frame_period(20);
+        assert_eq!(watchdog.deadline(), MIN_DEADLINE_MS);
+    }
+
+    #[test]
+    fn pause_suspends_the_deadline() {
+        let mut watchdog = StallWatchdog::new();
+        let (mut time, mut count) = (0, 0);
+        run(&mut watchdog, &mut time, &mut count, 100, |_| true);
+
+        watchdog.pause();
+        let verdict = run(&mut watchdog, &mut time, &mut count, 5000, |_| false);
+        assert_eq!(verdict, Verdict::Healthy);
+
+        // The display gets the full deadline to start again
+        watchdog.resume(time);
+        let verdict = run(
+            &mut watchdog,
+            &mut time,
+            &mut count,
+            MIN_DEADLINE_MS - 10,
+            |_| false,
+        );
+        assert_eq!(verdict, Verdict::Healthy);
+        let verdict = run(&mut watchdog, &mut time, &mut count, 10, |_| false);
+        assert_eq!(verdict, Verdict::Stalled);
+    }
+}
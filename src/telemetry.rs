@@ -0,0 +1,218 @@
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use esp_wifi_sys::include::{esp_wifi_sta_get_ap_info, wifi_ap_record_t};
+
+use crate::{ntptime, scopeclock};
+
+/// Counters and measurements of the display.
+/// Lock free to allow updates from inside the interrupt handlers.
+pub struct Telemetry {
+    /// Frames completely drawn by the DMA, including repeated ones.
+    /// Only new frames are counted with circular DMA.
+    pub frames_shown: AtomicU32,
+    /// Frames which had to be repeated as no new one was drawn in time
+    pub frames_missed: AtomicU32,
+    /// DMA transfers started for parts of a frame.
+    /// Descriptors passed by the DMA with circular DMA.
+    pub parts_transferred: AtomicU32,
+    /// Measured frame rate in mHz
+    pub frame_rate: AtomicU32,
+    /// Amount of samples of the last drawn frame without padding
+    pub frame_samples: AtomicU32,
+    /// Frames which were too long to reach the target frame rate
+    pub frame_overruns: AtomicU32,
+    /// Frames which didn't fit into the frame buffer and were cut off
+    pub frames_truncated: AtomicU32,
+    /// CPU cycles from the blanking interrupt to enabling the beam for the next part
+    pub beam_on_latency: AtomicU32,
+    /// Maximum of beam_on_latency
+    pub beam_on_latency_max: AtomicU32,
+    /// Maximum time in µs required to draw a frame
+    pub draw_time_max: AtomicU32,
+}
+
+pub static TELEMETRY: Telemetry = Telemetry {
+    frames_shown: AtomicU32::new(0),
+    frames_missed: AtomicU32::new(0),
+    parts_transferred: AtomicU32::new(0),
+    frame_rate: AtomicU32::new(0),
+    frame_samples: AtomicU32::new(0),
+    frame_overruns: AtomicU32::new(0),
+    frames_truncated: AtomicU32::new(0),
+    beam_on_latency: AtomicU32::new(0),
+    beam_on_latency_max: AtomicU32::new(0),
+    draw_time_max: AtomicU32::new(0),
+};
+
+/// Written by the NMI handler in high_level.S with the cycle count at the end of a part
+#[no_mangle]
+pub static SCOPECLOCK_NMI_CYCLES: AtomicU32 = AtomicU32::new(0);
+
+/// Signal strength of the access point in dBm. None if not connected.
+pub fn wifi_rssi() -> Option<i8> {
+    // SAFETY: Only reads the state of the WiFi driver into the record
+    unsafe {
+        let mut record: wifi_ap_record_t = core::mem::zeroed();
+        (esp_wifi_sta_get_ap_info(&mut record) == 0).then_some(record.rssi)
+    }
+}
+
+/// Reads the cycle counter of the CPU
+#[inline(always)]
+pub fn cycle_count() -> u32 {
+    let cycles: u32;
+    unsafe { asm!("rsr.ccount {0}", out(reg) cycles) };
+    cycles
+}
+
+impl Telemetry {
+    /// Records the latency between the last blanking interrupt and now
+    pub fn record_beam_on(&self) {
+        let latency = cycle_count().wrapping_sub(SCOPECLOCK_NMI_CYCLES.load(Ordering::Relaxed));
+        self.beam_on_latency.store(latency, Ordering::Relaxed);
+        self.beam_on_latency_max
+            .fetch_max(latency, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            frames_shown: self.frames_shown.load(Ordering::Relaxed),
+            frames_missed: self.frames_missed.load(Ordering::Relaxed),
+            parts_transferred: self.parts_transferred.load(Ordering::Relaxed),
+            frame_rate: self.frame_rate.load(Ordering::Relaxed),
+            frame_samples: self.frame_samples.load(Ordering::Relaxed),
+            frame_overruns: self.frame_overruns.load(Ordering::Relaxed),
+            frames_truncated: self.frames_truncated.load(Ordering::Relaxed),
+            beam_on_latency: self.beam_on_latency.load(Ordering::Relaxed),
+            beam_on_latency_max: self.beam_on_latency_max.load(Ordering::Relaxed),
+            draw_time_max: self.draw_time_max.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Copy of all values at one point in time
+#[derive(Clone, Copy, Debug)]
+pub struct Snapshot {
+    pub frames_shown: u32,
+    pub frames_missed: u32,
+    pub parts_transferred: u32,
+    pub frame_rate: u32,
+    pub frame_samples: u32,
+    pub frame_overruns: u32,
+    pub frames_truncated: u32,
+    pub beam_on_latency: u32,
+    pub beam_on_latency_max: u32,
+    pub draw_time_max: u32,
+}
+
+impl Snapshot {
+    /// All values together with their names
+    pub fn fields(&self) -> [(&'static str, u32); 10] {
+        [
+            ("frames_shown", self.frames_shown),
+            ("frames_missed", self.frames_missed),
+            ("parts_transferred", self.parts_transferred),
+            ("frame_rate_mhz", self.frame_rate),
+            ("frame_samples", self.frame_samples),
+            ("frame_overruns", self.frame_overruns),
+            ("frames_truncated", self.frames_truncated),
+            ("beam_on_latency_cycles", self.beam_on_latency),
+            ("beam_on_latency_max_cycles", self.beam_on_latency_max),
+            ("draw_time_max_us", self.draw_time_max),
+        ]
+    }
+
+    /// Formats the values as JSON object
+    pub fn json(&self) -> Json<'_> {
+        Json(self)
+    }
+}
+
+/// Formats as one line of name=value pairs for the serial console
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, value)) in self.fields().iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Json<'a>(&'a Snapshot);
+
+impl fmt::Display for Json<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (name, value)) in self.0.fields().iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "\"{}\":{}", name, value)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// State of the whole device besides the display
+#[derive(Clone, Copy, Debug)]
+pub struct DeviceStatus {
+    pub uptime_secs: u32,
+    /// Offset to the NTP server measured last in µs
+    pub ntp_offset: i32,
+    /// Seconds since the last successful NTP request
+    pub ntp_age_secs: Option<u32>,
+    pub rssi: Option<i8>,
+    /// Free bytes of the heap
+    pub free_heap: usize,
+    /// Test pattern or display mode
+    pub face: &'static str,
+    pub display: Snapshot,
+}
+
+impl DeviceStatus {
+    pub fn capture() -> DeviceStatus {
+        let uptime_secs = embassy_time::Instant::now().as_secs() as u32;
+        let last_sync = ntptime::LAST_SYNC.load(Ordering::Relaxed);
+        DeviceStatus {
+            uptime_secs,
+            ntp_offset: ntptime::LAST_OFFSET.load(Ordering::Relaxed),
+            ntp_age_secs: (last_sync != ntptime::NEVER_SYNCED)
+                .then(|| uptime_secs.saturating_sub(last_sync)),
+            rssi: wifi_rssi(),
+            free_heap: crate::ALLOCATOR.free(),
+            face: scopeclock::current_face(),
+            display: TELEMETRY.snapshot(),
+        }
+    }
+}
+
+/// Formats as JSON object. Unknown values are null.
+impl fmt::Display for DeviceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{\"uptime_s\":{},\"ntp_offset_us\":{},",
+            self.uptime_secs, self.ntp_offset
+        )?;
+        match self.ntp_age_secs {
+            Some(age) => write!(f, "\"ntp_last_sync_s_ago\":{},", age)?,
+            None => write!(f, "\"ntp_last_sync_s_ago\":null,")?,
+        }
+        match self.rssi {
+            Some(rssi) => write!(f, "\"rssi_dbm\":{},", rssi)?,
+            None => write!(f, "\"rssi_dbm\":null,")?,
+        }
+        write!(
+            f,
+            "\"free_heap\":{},\"face\":\"{}\",\"display\":{}}}",
+            self.free_heap,
+            self.face,
+            self.display.json()
+        )
+    }
+}
@@ -0,0 +1,62 @@
+//! Screens to set up the scope and to tune the beam timing.
+//!
+//! Only the selection is kept here. The screens are drawn by test_pattern_drawing.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TestPattern {
+    /// Grid of lines over the full range. Shows position, size and distortion.
+    Crosshatch = 0,
+    /// Concentric circles around the center. Shows the ratio of X and Y gain.
+    Circles = 1,
+    /// Equally spaced dots along both axes and a diagonal. Shows the linearity of the DACs.
+    Ramp = 2,
+    /// Matrix of single dots to adjust the focus
+    Dots = 3,
+    /// Markers in the corners of the full DAC range
+    Corners = 4,
+    /// Dashed lines. The beam is blanked after every dash,
+    /// so a late blanking shows up as a tail behind the dashes.
+    BlankTiming = 5,
+}
+
+impl TestPattern {
+    const ALL: [TestPattern; 6] = [
+        TestPattern::Crosshatch,
+        TestPattern::Circles,
+        TestPattern::Ramp,
+        TestPattern::Dots,
+        TestPattern::Corners,
+        TestPattern::BlankTiming,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TestPattern::Crosshatch => "crosshatch",
+            TestPattern::Circles => "circles",
+            TestPattern::Ramp => "ramp",
+            TestPattern::Dots => "dots",
+            TestPattern::Corners => "corners",
+            TestPattern::BlankTiming => "blank_timing",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<TestPattern> {
+        Self::ALL.into_iter().find(|pattern| pattern.name() == name)
+    }
+}
+
+/// Selected TestPattern or NONE to show the clock
+static SELECTED: AtomicU8 = AtomicU8::new(NONE);
+const NONE: u8 = 0xff;
+
+pub fn select(pattern: Option<TestPattern>) {
+    let value = pattern.map(|p| p as u8).unwrap_or(NONE);
+    SELECTED.store(value, Ordering::Relaxed);
+}
+
+pub fn selected() -> Option<TestPattern> {
+    let value = SELECTED.load(Ordering::Relaxed);
+    TestPattern::ALL.into_iter().find(|p| *p as u8 == value)
+}
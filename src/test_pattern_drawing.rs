@@ -0,0 +1,89 @@
+//! Draws the screens selected by test_pattern.
+//!
+//! Drawn through Picture like the clock face, so the calibration applies,
+//! but without the transformation of the screensaver.
+
+use crate::picture::{Picture, MAX_COORDINATE};
+use crate::test_pattern::TestPattern;
+
+/// Highest logical coordinate
+const MAX: isize = MAX_COORDINATE as isize;
+const CENTER: isize = MAX / 2;
+
+/// Exposure of dots as used for the minute marks of the clock face
+const DOT_EXPOSURE: usize = 14;
+
+/// Positions of n lines or dots spread over the full range including both ends
+fn spread(n: isize) -> impl Iterator<Item = isize> {
+    (0..n).map(move |i| i * MAX / (n - 1))
+}
+
+fn draw_crosshatch(pic: &mut Picture) {
+    // At high brightness the last lines don't fit and are dropped by Picture
+    for position in spread(9) {
+        pic.add_line((position, 0), (position, MAX));
+        pic.add_line((0, position), (MAX, position));
+    }
+}
+
+fn draw_circles(pic: &mut Picture) {
+    for radius in (1..=5).map(|i| i * CENTER / 5) {
+        pic.add_circle((CENTER, CENTER), radius as f32, 8 + radius as usize / 4);
+    }
+}
+
+fn draw_ramp(pic: &mut Picture) {
+    for position in (0..=MAX).step_by(16) {
+        pic.add_dot2((position, CENTER), DOT_EXPOSURE);
+        pic.add_dot2((CENTER, position), DOT_EXPOSURE);
+    }
+    pic.add_line((0, 0), (MAX, MAX));
+}
+
+fn draw_dots(pic: &mut Picture) {
+    for x in spread(9) {
+        for y in spread(9) {
+            pic.add_dot2((x, y), DOT_EXPOSURE);
+        }
+    }
+}
+
+fn draw_corners(pic: &mut Picture) {
+    const LENGTH: isize = 40;
+    for (x, y, dx, dy) in [
+        (0, 0, 1, 1),
+        (MAX, 0, -1, 1),
+        (MAX, MAX, -1, -1),
+        (0, MAX, 1, -1),
+    ] {
+        pic.add_open_polygon(&[(x + dx * LENGTH, y), (x, y), (x, y + dy * LENGTH)]);
+    }
+}
+
+fn draw_blank_timing(pic: &mut Picture) {
+    const DASH: isize = 20;
+    for y in (1..=5).map(|i| i * MAX / 6) {
+        let mut x = DASH;
+        while x + DASH < MAX {
+            pic.add_line((x, y), (x + DASH, y));
+            x += 2 * DASH;
+        }
+    }
+}
+
+pub fn draw_test_pattern<'a>(tx_buffer: &'a mut [u8], pattern: TestPattern) -> Picture<'a> {
+    let mut pic = Picture::new(tx_buffer);
+    match pattern {
+        TestPattern::Crosshatch => draw_crosshatch(&mut pic),
+        TestPattern::Circles => draw_circles(&mut pic),
+        TestPattern::Ramp => draw_ramp(&mut pic),
+        TestPattern::Dots => draw_dots(&mut pic),
+        TestPattern::Corners => draw_corners(&mut pic),
+        TestPattern::BlankTiming => draw_blank_timing(&mut pic),
+    }
+    // The dashes must stay in drawing order and direction to compare their tails
+    if pattern != TestPattern::BlankTiming {
+        pic.frame.optimize_path(0);
+    }
+    pic
+}
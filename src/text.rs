@@ -0,0 +1,535 @@
+//! Layout and rendering of free text with a simple stroke font.
+//!
+//! font::FONT and Picture::draw_font can't be extended for this. The drawings of
+//! FONT are generated by tool/ from numbers.svg and each one is centered on its own
+//! bounding box, which loses the baseline and the advance width the layout needs
+//! for wrapping and scrolling. draw_font also draws every stroke as polyline, so the
+//! dots of '.', ':' or '!' would only get a single sample.
+//!
+//! This font covers letters, digits and common punctuation. Glyphs are drawn on a
+//! grid with the baseline at 0 and capitals reaching up to 6. Lower case is shown
+//! as upper case. Y goes up, as on the scope.
+
+use alloc::vec::Vec;
+
+use crate::picture::Picture;
+
+type Point = (isize, isize);
+
+/// Height of capitals in grid units
+pub const CAP_HEIGHT: i32 = 6;
+
+/// Space between two glyphs in grid units
+const GAP: i32 = 2;
+
+/// Exposure of strokes consisting of a single point
+const DOT_EXPOSURE: usize = 14;
+
+struct Glyph {
+    /// Horizontal extent of the strokes in grid units
+    width: i8,
+    /// Polylines. A single point is drawn as a dot.
+    strokes: &'static [&'static [(i8, i8)]],
+}
+
+const O_STROKE: &[(i8, i8)] = &[
+    (1, 0),
+    (0, 1),
+    (0, 5),
+    (1, 6),
+    (3, 6),
+    (4, 5),
+    (4, 1),
+    (3, 0),
+    (1, 0),
+];
+const P_STROKE: &[(i8, i8)] = &[(0, 0), (0, 6), (3, 6), (4, 5), (4, 4), (3, 3), (0, 3)];
+const U_STROKE: &[(i8, i8)] = &[(0, 6), (0, 1), (1, 0), (3, 0), (4, 1), (4, 6)];
+
+const fn glyph(width: i8, strokes: &'static [&'static [(i8, i8)]]) -> Glyph {
+    Glyph { width, strokes }
+}
+
+const GLYPHS: &[(char, Glyph)] = &[
+    (' ', glyph(1, &[])),
+    (
+        'A',
+        glyph(
+            4,
+            &[&[(0, 0), (0, 4), (2, 6), (4, 4), (4, 0)], &[(0, 3), (4, 3)]],
+        ),
+    ),
+    (
+        'B',
+        glyph(
+            4,
+            &[
+                &[(0, 0), (0, 6), (3, 6), (4, 5), (4, 4), (3, 3), (0, 3)],
+                &[(3, 3), (4, 2), (4, 1), (3, 0), (0, 0)],
+            ],
+        ),
+    ),
+    (
+        'C',
+        glyph(
+            4,
+            &[&[
+                (4, 5),
+                (3, 6),
+                (1, 6),
+                (0, 5),
+                (0, 1),
+                (1, 0),
+                (3, 0),
+                (4, 1),
+            ]],
+        ),
+    ),
+    (
+        'D',
+        glyph(
+            4,
+            &[&[(0, 0), (0, 6), (2, 6), (4, 4), (4, 2), (2, 0), (0, 0)]],
+        ),
+    ),
+    (
+        'E',
+        glyph(4, &[&[(4, 6), (0, 6), (0, 0), (4, 0)], &[(0, 3), (3, 3)]]),
+    ),
+    (
+        'F',
+        glyph(4, &[&[(4, 6), (0, 6), (0, 0)], &[(0, 3), (3, 3)]]),
+    ),
+    (
+        'G',
+        glyph(
+            4,
+            &[&[
+                (4, 5),
+                (3, 6),
+                (1, 6),
+                (0, 5),
+                (0, 1),
+                (1, 0),
+                (3, 0),
+                (4, 1),
+                (4, 3),
+                (2, 3),
+            ]],
+        ),
+    ),
+    (
+        'H',
+        glyph(
+            4,
+            &[&[(0, 0), (0, 6)], &[(4, 0), (4, 6)], &[(0, 3), (4, 3)]],
+        ),
+    ),
+    (
+        'I',
+        glyph(
+            2,
+            &[&[(0, 6), (2, 6)], &[(1, 6), (1, 0)], &[(0, 0), (2, 0)]],
+        ),
+    ),
+    ('J', glyph(4, &[&[(4, 6), (4, 1), (3, 0), (1, 0), (0, 1)]])),
+    (
+        'K',
+        glyph(
+            4,
+            &[&[(0, 0), (0, 6)], &[(4, 6), (0, 2)], &[(1, 3), (4, 0)]],
+        ),
+    ),
+    ('L', glyph(4, &[&[(0, 6), (0, 0), (4, 0)]])),
+    ('M', glyph(4, &[&[(0, 0), (0, 6), (2, 3), (4, 6), (4, 0)]])),
+    ('N', glyph(4, &[&[(0, 0), (0, 6), (4, 0), (4, 6)]])),
+    ('O', glyph(4, &[O_STROKE])),
+    ('P', glyph(4, &[P_STROKE])),
+    ('Q', glyph(4, &[O_STROKE, &[(2, 2), (4, 0)]])),
+    ('R', glyph(4, &[P_STROKE, &[(2, 3), (4, 0)]])),
+    (
+        'S',
+        glyph(
+            4,
+            &[&[
+                (4, 5),
+                (3, 6),
+                (1, 6),
+                (0, 5),
+                (0, 4),
+                (1, 3),
+                (3, 3),
+                (4, 2),
+                (4, 1),
+                (3, 0),
+                (1, 0),
+                (0, 1),
+            ]],
+        ),
+    ),
+    ('T', glyph(4, &[&[(0, 6), (4, 6)], &[(2, 6), (2, 0)]])),
+    ('U', glyph(4, &[U_STROKE])),
+    ('V', glyph(4, &[&[(0, 6), (2, 0), (4, 6)]])),
+    ('W', glyph(4, &[&[(0, 6), (1, 0), (2, 4), (3, 0), (4, 6)]])),
+    ('X', glyph(4, &[&[(0, 0), (4, 6)], &[(0, 6), (4, 0)]])),
+    (
+        'Y',
+        glyph(4, &[&[(0, 6), (2, 3), (4, 6)], &[(2, 3), (2, 0)]]),
+    ),
+    ('Z', glyph(4, &[&[(0, 6), (4, 6), (0, 0), (4, 0)]])),
+    (
+        'Ä',
+        glyph(
+            4,
+            &[
+                &[(0, 0), (0, 4), (2, 6), (4, 4), (4, 0)],
+                &[(0, 3), (4, 3)],
+                &[(1, 8)],
+                &[(3, 8)],
+            ],
+        ),
+    ),
+    ('Ö', glyph(4, &[O_STROKE, &[(1, 8)], &[(3, 8)]])),
+    ('Ü', glyph(4, &[U_STROKE, &[(1, 8)], &[(3, 8)]])),
+    ('0', glyph(4, &[O_STROKE, &[(0, 1), (4, 5)]])),
+    (
+        '1',
+        glyph(2, &[&[(0, 5), (1, 6), (1, 0)], &[(0, 0), (2, 0)]]),
+    ),
+    (
+        '2',
+        glyph(
+            4,
+            &[&[(0, 5), (1, 6), (3, 6), (4, 5), (4, 4), (0, 0), (4, 0)]],
+        ),
+    ),
+    (
+        '3',
+        glyph(
+            4,
+            &[
+                &[(0, 5), (1, 6), (3, 6), (4, 5), (4, 4), (3, 3), (1, 3)],
+                &[(3, 3), (4, 2), (4, 1), (3, 0), (1, 0), (0, 1)],
+            ],
+        ),
+    ),
+    ('4', glyph(4, &[&[(3, 0), (3, 6), (0, 2), (4, 2)]])),
+    (
+        '5',
+        glyph(
+            4,
+            &[&[
+                (4, 6),
+                (0, 6),
+                (0, 3),
+                (3, 3),
+                (4, 2),
+                (4, 1),
+                (3, 0),
+                (0, 0),
+            ]],
+        ),
+    ),
+    (
+        '6',
+        glyph(
+            4,
+            &[&[
+                (4, 5),
+                (3, 6),
+                (1, 6),
+                (0, 5),
+                (0, 1),
+                (1, 0),
+                (3, 0),
+                (4, 1),
+                (4, 2),
+                (3, 3),
+                (0, 3),
+            ]],
+        ),
+    ),
+    ('7', glyph(4, &[&[(0, 6), (4, 6), (1, 0)]])),
+    (
+        '8',
+        glyph(
+            4,
+            &[
+                &[
+                    (1, 3),
+                    (0, 4),
+                    (0, 5),
+                    (1, 6),
+                    (3, 6),
+                    (4, 5),
+                    (4, 4),
+                    (3, 3),
+                    (1, 3),
+                ],
+                &[
+                    (1, 3),
+                    (0, 2),
+                    (0, 1),
+                    (1, 0),
+                    (3, 0),
+                    (4, 1),
+                    (4, 2),
+                    (3, 3),
+                ],
+            ],
+        ),
+    ),
+    (
+        '9',
+        glyph(
+            4,
+            &[&[
+                (4, 3),
+                (1, 3),
+                (0, 4),
+                (0, 5),
+                (1, 6),
+                (3, 6),
+                (4, 5),
+                (4, 1),
+                (3, 0),
+                (1, 0),
+                (0, 1),
+            ]],
+        ),
+    ),
+    ('.', glyph(0, &[&[(0, 0)]])),
+    (',', glyph(1, &[&[(1, 1), (0, -1)]])),
+    (':', glyph(0, &[&[(0, 1)], &[(0, 5)]])),
+    (';', glyph(1, &[&[(1, 5)], &[(1, 1), (0, -1)]])),
+    ('!', glyph(0, &[&[(0, 6), (0, 2)], &[(0, 0)]])),
+    (
+        '?',
+        glyph(
+            4,
+            &[
+                &[(0, 5), (1, 6), (3, 6), (4, 5), (4, 4), (2, 3), (2, 2)],
+                &[(2, 0)],
+            ],
+        ),
+    ),
+    ('-', glyph(3, &[&[(0, 3), (3, 3)]])),
+    ('+', glyph(4, &[&[(0, 3), (4, 3)], &[(2, 1), (2, 5)]])),
+    ('=', glyph(4, &[&[(0, 2), (4, 2)], &[(0, 4), (4, 4)]])),
+    ('/', glyph(4, &[&[(0, 0), (4, 6)]])),
+    ('\'', glyph(0, &[&[(0, 6), (0, 4)]])),
+    ('"', glyph(1, &[&[(0, 6), (0, 4)], &[(1, 6), (1, 4)]])),
+    ('(', glyph(1, &[&[(1, 6), (0, 4), (0, 2), (1, 0)]])),
+    (')', glyph(1, &[&[(0, 6), (1, 4), (1, 2), (0, 0)]])),
+    ('<', glyph(3, &[&[(3, 5), (0, 3), (3, 1)]])),
+    ('>', glyph(3, &[&[(0, 5), (3, 3), (0, 1)]])),
+    ('%', glyph(4, &[&[(0, 0), (4, 6)], &[(0, 6)], &[(4, 0)]])),
+    (
+        '*',
+        glyph(
+            4,
+            &[&[(2, 1), (2, 5)], &[(0, 2), (4, 4)], &[(0, 4), (4, 2)]],
+        ),
+    ),
+    (
+        '#',
+        glyph(
+            4,
+            &[
+                &[(1, 0), (1, 6)],
+                &[(3, 0), (3, 6)],
+                &[(0, 2), (4, 2)],
+                &[(0, 4), (4, 4)],
+            ],
+        ),
+    ),
+    ('_', glyph(4, &[&[(0, 0), (4, 0)]])),
+];
+
+/// Pairs of glyphs moved closer together, as their shapes leave a visible gap
+const KERNING: &[(char, char, i8)] = &[
+    ('A', 'T', -1),
+    ('A', 'V', -1),
+    ('A', 'W', -1),
+    ('A', 'Y', -1),
+    ('F', 'A', -1),
+    ('F', '.', -2),
+    ('F', ',', -1),
+    ('L', 'T', -2),
+    ('L', 'V', -2),
+    ('L', 'W', -1),
+    ('L', 'Y', -2),
+    ('P', 'A', -1),
+    ('P', '.', -2),
+    ('P', ',', -1),
+    ('T', 'A', -1),
+    ('T', '.', -2),
+    ('T', ',', -1),
+    ('V', 'A', -1),
+    ('V', '.', -2),
+    ('V', ',', -1),
+    ('W', 'A', -1),
+    ('Y', 'A', -1),
+    ('Y', '.', -2),
+    ('Y', ',', -1),
+];
+
+/// Lower case is shown as upper case
+fn normalize(c: char) -> char {
+    c.to_uppercase().next().unwrap_or(c)
+}
+
+/// Unknown characters are shown as '?'
+fn lookup(c: char) -> &'static Glyph {
+    let c = normalize(c);
+    GLYPHS
+        .iter()
+        .find(|(glyph_char, _)| *glyph_char == c)
+        .or_else(|| GLYPHS.iter().find(|(glyph_char, _)| *glyph_char == '?'))
+        .map(|(_, glyph)| glyph)
+        .unwrap()
+}
+
+fn kerning(left: char, right: char) -> i32 {
+    let (left, right) = (normalize(left), normalize(right));
+    KERNING
+        .iter()
+        .find(|(l, r, _)| *l == left && *r == right)
+        .map_or(0, |(_, _, adjust)| *adjust as i32)
+}
+
+/// Horizontal start of every glyph of a line in grid units, together with the total width
+fn advances(line: &str) -> (Vec<(char, i32)>, i32) {
+    let mut positions = Vec::with_capacity(line.len());
+    let mut x = 0;
+    let mut previous: Option<char> = None;
+    for c in line.chars() {
+        if let Some(previous) = previous {
+            x += lookup(previous).width as i32 + GAP + kerning(previous, c);
+        }
+        positions.push((c, x));
+        previous = Some(c);
+    }
+    let width = previous.map_or(0, |c| x + lookup(c).width as i32);
+    (positions, width)
+}
+
+/// Width of a single line in grid units
+pub fn width(line: &str) -> i32 {
+    advances(line).1
+}
+
+/// Splits the text into lines not wider than max_width grid units.
+/// Breaks at spaces and at '\n'. Words which are too long on their own are split.
+pub fn wrap(text: &str, max_width: i32) -> Vec<&str> {
+    let mut lines = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut start: Option<usize> = None;
+        let mut end = 0;
+        for (word_start, word) in words(paragraph) {
+            let word_end = word_start + word.len();
+            if let Some(line_start) = start {
+                if width(&paragraph[line_start..word_end]) <= max_width {
+                    end = word_end;
+                    continue;
+                }
+                lines.push(&paragraph[line_start..end]);
+            }
+            // The word starts a new line
+            let mut word_start = word_start;
+            while width(&paragraph[word_start..word_end]) > max_width {
+                let split = split_point(&paragraph[word_start..word_end], max_width);
+                lines.push(&paragraph[word_start..word_start + split]);
+                word_start += split;
+            }
+            start = Some(word_start);
+            end = word_end;
+        }
+        match start {
+            Some(line_start) => lines.push(&paragraph[line_start..end]),
+            None => lines.push(""),
+        }
+    }
+    lines
+}
+
+/// Words of a paragraph with their byte offset
+fn words(paragraph: &str) -> impl Iterator<Item = (usize, &str)> {
+    paragraph
+        .split(' ')
+        .scan(0, |offset, word| {
+            let start = *offset;
+            *offset += word.len() + 1;
+            Some((start, word))
+        })
+        .filter(|(_, word)| !word.is_empty())
+}
+
+/// Byte index up to which a word fits into max_width. At least one character.
+fn split_point(word: &str, max_width: i32) -> usize {
+    let mut split = word.chars().next().map_or(0, char::len_utf8);
+    for (index, c) in word.char_indices().skip(1) {
+        let next = index + c.len_utf8();
+        if width(&word[..next]) > max_width {
+            break;
+        }
+        split = next;
+    }
+    split
+}
+
+/// A glyph placed on the screen in logical coordinates
+pub struct PlacedGlyph {
+    glyph: &'static Glyph,
+    origin: Point,
+    scale: f32,
+}
+
+impl PlacedGlyph {
+    fn point(&self, (x, y): (i8, i8)) -> Point {
+        (
+            self.origin.0 + libm::roundf(x as f32 * self.scale) as isize,
+            self.origin.1 + libm::roundf(y as f32 * self.scale) as isize,
+        )
+    }
+
+    /// Horizontal extent in logical coordinates
+    pub fn span(&self) -> (isize, isize) {
+        let right = self.point((self.glyph.width, 0)).0;
+        (self.origin.0, right)
+    }
+
+    /// Length of all strokes in logical units
+    pub fn length(&self) -> f32 {
+        let segments = self.glyph.strokes.iter().flat_map(|s| s.windows(2));
+        let grid_length: f32 = segments
+            .map(|pair| {
+                let dx = (pair[1].0 - pair[0].0) as f32;
+                let dy = (pair[1].1 - pair[0].1) as f32;
+                libm::sqrtf(dx * dx + dy * dy)
+            })
+            .sum();
+        grid_length * self.scale
+    }
+
+    pub fn draw(&self, pic: &mut Picture) {
+        for stroke in self.glyph.strokes {
+            let points: Vec<Point> = stroke.iter().map(|p| self.point(*p)).collect();
+            match points.as_slice() {
+                [dot] => pic.add_dot2(*dot, DOT_EXPOSURE),
+                points => pic.add_open_polygon(points),
+            }
+        }
+    }
+}
+
+/// Places a line with its baseline starting at origin.
+/// scale is the size of a grid unit in logical coordinates.
+pub fn place(line: &str, origin: Point, scale: f32) -> impl Iterator<Item = PlacedGlyph> {
+    let (positions, _) = advances(line);
+    positions.into_iter().map(move |(c, x)| PlacedGlyph {
+        glyph: lookup(c),
+        origin: (origin.0 + libm::roundf(x as f32 * scale) as isize, origin.1),
+        scale,
+    })
+}
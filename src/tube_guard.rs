@@ -0,0 +1,142 @@
+use core::cell::RefCell;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::Ordering;
+
+use critical_section::Mutex;
+
+#[path = "util.rs"]
+mod examples_util;
+
+use esp_println::println;
+use examples_util::hal;
+use hal::interrupt::{self, Priority};
+use hal::peripherals::{Interrupt, TIMG1};
+use hal::prelude::*;
+use hal::timer::{Timer, Timer1};
+
+use crate::display_backend::{ActiveBackend, DisplayBackend, I2S_INT_ENA_OFFSET};
+use crate::limits::FRAME_BUFFER_BYTES;
+use crate::picture;
+use crate::stall_watchdog::{StallWatchdog, Verdict};
+use crate::telemetry::TELEMETRY;
+
+// Registers are accessed directly, as the drivers owning them can't be trusted
+// anymore when we have to protect the tube.
+const GPIO_OUT1_W1TS_REG: *mut u32 = 0x3ff44014 as *mut u32;
+const I2S_INT_ENA_REG: *mut u32 = (ActiveBackend::I2S_BASE + I2S_INT_ENA_OFFSET) as *mut u32;
+const SENS_SAR_DAC_CTRL1_REG: *mut u32 = 0x3ff48898 as *mut u32;
+const RTC_IO_PAD_DAC1_REG: *mut u32 = 0x3ff48484 as *mut u32;
+const RTC_IO_PAD_DAC2_REG: *mut u32 = 0x3ff48488 as *mut u32;
+
+/// Z Blanking is on GPIO 32, which is bit 0 of the second output register
+const Z_BLANK_BIT: u32 = 1 << 0;
+/// Connects the DACs to the I2S
+const SENS_DAC_DIG_FORCE: u32 = 1 << 22;
+/// Output value of a DAC pad
+const RTC_IO_PDAC_DAC_MASK: u32 = 0xff << 19;
+
+/// Period in which the watchdog checks the display
+const CHECK_PERIOD_MS: u64 = 10;
+
+/// Samples in a full frame buffer. No part and no frame takes longer to show.
+const FRAME_BUFFER_SAMPLES: u64 = (FRAME_BUFFER_BYTES / ActiveBackend::SAMPLE_BYTES) as u64;
+
+/// Turns the beam off. Safe to call from anywhere at any time.
+pub fn blank_beam() {
+    unsafe {
+        // Avoid that the display interrupt enables the beam again
+        write_volatile(I2S_INT_ENA_REG, 0);
+        write_volatile(GPIO_OUT1_W1TS_REG, Z_BLANK_BIT);
+    }
+}
+
+/// Disconnects both internal DACs from the DMA and moves the beam into the corner.
+/// Has no effect on external DACs.
+pub fn park_dacs() {
+    unsafe {
+        let ctrl = read_volatile(SENS_SAR_DAC_CTRL1_REG);
+        write_volatile(SENS_SAR_DAC_CTRL1_REG, ctrl & !SENS_DAC_DIG_FORCE);
+
+        for pad in [RTC_IO_PAD_DAC1_REG, RTC_IO_PAD_DAC2_REG] {
+            let value = read_volatile(pad);
+            write_volatile(pad, value & !RTC_IO_PDAC_DAC_MASK);
+        }
+    }
+}
+
+struct Guard {
+    timer: Timer<Timer1<TIMG1>>,
+    watchdog: StallWatchdog,
+    now: u64,
+}
+
+static GUARD: Mutex<RefCell<Option<Guard>>> = Mutex::new(RefCell::new(None));
+
+/// Starts watching the display using a timer interrupt.
+/// Independent of the executor to still work when a task hangs.
+pub fn tube_guard_init(mut timer: Timer<Timer1<TIMG1>>) {
+    timer.start(CHECK_PERIOD_MS.millis());
+    timer.listen();
+
+    critical_section::with(|cs| {
+        GUARD.borrow_ref_mut(cs).replace(Guard {
+            timer,
+            watchdog: StallWatchdog::new(),
+            now: 0,
+        });
+    });
+
+    interrupt::enable(Interrupt::TG1_T1_LEVEL, Priority::Priority3).unwrap();
+}
+
+/// Stops expecting progress of the display, e.g. while it is parked
+pub fn pause_watchdog() {
+    critical_section::with(|cs| {
+        if let Some(guard) = GUARD.borrow_ref_mut(cs).as_mut() {
+            guard.watchdog.pause();
+        }
+    });
+}
+
+/// Expects progress of the display again
+pub fn resume_watchdog() {
+    critical_section::with(|cs| {
+        if let Some(guard) = GUARD.borrow_ref_mut(cs).as_mut() {
+            guard.watchdog.resume(guard.now);
+        }
+    });
+}
+
+#[ram]
+#[interrupt]
+fn TG1_T1_LEVEL() {
+    critical_section::with(|cs| {
+        let mut guard = GUARD.borrow_ref_mut(cs);
+        let guard = guard.as_mut().unwrap();
+
+        guard.timer.clear_interrupt();
+        guard.timer.start(CHECK_PERIOD_MS.millis());
+
+        guard.now += CHECK_PERIOD_MS;
+        // Lower sample rates take longer for the same frame
+        let sample_rate = picture::SAMPLE_RATE.load(Ordering::Relaxed).max(1) as u64;
+        guard
+            .watchdog
+            .set_frame_period(FRAME_BUFFER_SAMPLES * 1000 / sample_rate);
+        let count = TELEMETRY.parts_transferred.load(Ordering::Relaxed);
+        if guard.watchdog.check(guard.now, count) == Verdict::Stalled {
+            blank_beam();
+        }
+    });
+}
+
+/// Called by esp_backtrace on a panic or an exception before anything is printed.
+/// Protects the tube first, as printing the backtrace takes a while.
+#[no_mangle]
+fn custom_pre_backtrace() {
+    blank_beam();
+    park_dacs();
+
+    println!("");
+    println!("!! The beam was turned off.");
+}
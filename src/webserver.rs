@@ -0,0 +1,191 @@
+use alloc::format;
+use alloc::string::String;
+
+use embassy_net::tcp::TcpSocket;
+use embassy_net::Stack;
+
+use embassy_time::Duration;
+use esp_backtrace as _;
+use esp_println::println;
+
+use esp_wifi::wifi::{WifiDevice, WifiStaDevice};
+
+use crate::telemetry::TELEMETRY;
+use crate::{calibration, command, message, night_mode, settings, test_pattern};
+
+/// Decodes a query parameter value. '+' is a space and %XX an encoded byte.
+fn url_decode(value: &str) -> Option<String> {
+    let mut bytes = alloc::vec::Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(b) = input.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                let hex = core::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Finds the value of a parameter in a query like "a=1&b=2"
+fn query_value(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .and_then(|(_, value)| url_decode(value))
+}
+
+/// Lists the current night mode settings
+fn night_mode_status() -> String {
+    let mut body = String::new();
+    match night_mode::override_mode() {
+        Some(mode) => body += &format!("mode {}\n", mode.name()),
+        None => body += "mode auto\n",
+    }
+    for rule in night_mode::rules() {
+        body += &format!("rule {}\n", rule);
+    }
+    body
+}
+
+/// Names the shown test pattern
+fn test_pattern_status() -> String {
+    match test_pattern::selected() {
+        Some(pattern) => format!("pattern {}\n", pattern.name()),
+        None => String::from("pattern none\n"),
+    }
+}
+
+/// Shows the current message
+fn message_status() -> String {
+    match message::current() {
+        Some((text, _)) => format!("message {}\n", text),
+        None => String::from("no message\n"),
+    }
+}
+
+/// Commands which change the calibration or write the flash. Crawlers, browser
+/// prefetches and link previews only send GET requests, so these need POST.
+const POST_ONLY: [&str; 2] = ["calibration", "calibration_save"];
+
+/// Runs a command and answers with the given status or the reason of the rejection
+fn execute(
+    method: &str,
+    name: &str,
+    value: &str,
+    status: impl FnOnce() -> String,
+) -> (&'static str, String) {
+    if POST_ONLY.contains(&name) && method != "POST" {
+        return ("405 Method Not Allowed", format!("{} needs POST\n", name));
+    }
+    match settings::run(name, value.as_bytes()) {
+        Ok(()) => ("200 OK", status()),
+        Err(command::Error::UnknownCommand) => ("404 Not Found", String::from("Not found\n")),
+        Err(command::Error::Failed) => ("500 Internal Server Error", format!("{} failed\n", name)),
+        Err(e) => ("400 Bad Request", format!("Invalid {}: {}\n", name, e)),
+    }
+}
+
+/// Creates the answer for a request with the given method to the given path
+fn respond(method: &str, path: &str) -> (&'static str, String) {
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    match path {
+        // e.g. /display_mode?mode=off or /display_mode?mode=auto
+        "/display_mode" => match query_value(query, "mode") {
+            None => ("200 OK", night_mode_status()),
+            Some(mode) => execute(method, "display_mode", &mode, night_mode_status),
+        },
+        // e.g. /night_rules?rules=mon-fri+23:00-06:30+minimal;sat-sun+01:00-08:00+off
+        "/night_rules" => match query_value(query, "rules") {
+            None => ("200 OK", night_mode_status()),
+            Some(rules) => execute(method, "night_rules", &rules, night_mode_status),
+        },
+        // e.g. POST /calibration?gain_x=1.1&offset_y=-3&rotation=0.5
+        "/calibration" if query.is_empty() => ("200 OK", format!("{}", calibration::current())),
+        "/calibration" => match url_decode(query) {
+            Some(settings) => execute(method, "calibration", &settings, || {
+                format!("{}", calibration::current())
+            }),
+            None => ("400 Bad Request", String::from("Invalid calibration\n")),
+        },
+        // POST only. The flash is only written if the calibration changed.
+        "/calibration/save" => execute(method, "calibration_save", "", || String::from("Saved\n")),
+        // e.g. /test_pattern?pattern=crosshatch or /test_pattern?pattern=none
+        "/test_pattern" => match query_value(query, "pattern") {
+            None => ("200 OK", test_pattern_status()),
+            Some(pattern) => execute(method, "test_pattern", &pattern, test_pattern_status),
+        },
+        "/metrics" => {
+            let mut body = String::new();
+            for (name, value) in TELEMETRY.snapshot().fields() {
+                body += &format!("scopeclock_{} {}\n", name, value);
+            }
+            ("200 OK", body)
+        }
+        // e.g. /message?text=Standup+in+5 or /message?text= to remove it
+        "/message" => match query_value(query, "text") {
+            None => ("200 OK", message_status()),
+            Some(text) => execute(method, "message", &text, message_status),
+        },
+        // Any command accepted over MQTT, e.g. /set/brightness?value=150
+        _ if path.starts_with("/set/") => {
+            let value = query_value(query, "value").unwrap_or_default();
+            execute(method, &path["/set/".len()..], &value, || {
+                String::from("ok\n")
+            })
+        }
+        _ => ("404 Not Found", String::from("Not found\n")),
+    }
+}
+
+/// Very small HTTP server to access the clock from the network
+#[embassy_executor::task]
+pub async fn webserver_task(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+
+        if let Err(e) = socket.accept(80).await {
+            println!("HTTP accept error: {:?}", e);
+            continue;
+        }
+
+        let mut buf = [0; 512];
+        let n = match socket.read(&mut buf).await {
+            Ok(n) => n,
+            Err(e) => {
+                println!("HTTP read error: {:?}", e);
+                continue;
+            }
+        };
+
+        // Only the request line is of interest. e.g. "GET /metrics HTTP/1.1"
+        let request = core::str::from_utf8(&buf[..n]).unwrap_or("");
+        let mut request_line = request.split_whitespace();
+        let method = request_line.next().unwrap_or("GET");
+        let path = request_line.next().unwrap_or("/");
+        let (status, body) = respond(method, path);
+
+        let response = format!(
+            "HTTP/1.0 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+
+        use embedded_io_async::Write;
+        if let Err(e) = socket.write_all(response.as_bytes()).await {
+            println!("HTTP write error: {:?}", e);
+        }
+        let _ = socket.flush().await;
+        socket.close();
+    }
+}